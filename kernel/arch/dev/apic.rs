@@ -0,0 +1,324 @@
+//apic.rs
+//local APIC driver (xAPIC and x2APIC), and the APIC interrupt controller
+//built from the local APIC and the I/O APICs listed in the MADT
+
+use core::ptr;
+use alloc::vec::Vec;
+use spin::{Mutex, Once};
+use arch::dev::pic;
+use arch::dev::ioapic::IoApic;
+use arch::dev::interrupt_controller::InterruptController;
+use arch::x86_64::{cpuid, msr};
+use arch::x86_64::acpi;
+use arch::x86_64::acpi::madt::{Madt, Polarity, TriggerMode};
+use arch::x86_64::int::irq;
+use arch::x86_64::mem;
+use arch::x86_64::mem::{PhysicalAddress, VirtualAddress};
+use arch::x86_64::mem::entry::EntryFlags;
+
+//register offsets (xAPIC MMIO layout, x2APIC MSRs are 0x800 + offset / 16)
+pub const REG_ID: u32 = 0x020;
+pub const REG_VERSION: u32 = 0x030;
+pub const REG_TPR: u32 = 0x080;
+pub const REG_EOI: u32 = 0x0B0;
+pub const REG_SPURIOUS: u32 = 0x0F0;
+pub const REG_ISR: u32 = 0x100;
+pub const REG_ESR: u32 = 0x280;
+pub const REG_ICR_LOW: u32 = 0x300;
+pub const REG_ICR_HIGH: u32 = 0x310;
+pub const REG_LVT_TIMER: u32 = 0x320;
+pub const REG_LVT_LINT0: u32 = 0x350;
+pub const REG_LVT_LINT1: u32 = 0x360;
+pub const REG_LVT_ERROR: u32 = 0x370;
+pub const REG_TIMER_INITIAL: u32 = 0x380;
+pub const REG_TIMER_CURRENT: u32 = 0x390;
+pub const REG_TIMER_DIVIDE: u32 = 0x3E0;
+
+//IA32_APIC_BASE bits
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+//vector raised for spurious local APIC interrupts, its low nibble must be all ones
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+const SPURIOUS_ENABLE: u32 = 1 << 8;
+
+//interrupt command register bits
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+
+//local vector table bits
+pub const LVT_MASKED: u32 = 1 << 16;
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+const LVT_ACTIVE_LOW: u32 = 1 << 13;
+const LVT_LEVEL: u32 = 1 << 15;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ApicMode {
+    XApic(VirtualAddress),
+    X2Apic,
+}
+
+pub struct LocalApic {
+    mode: ApicMode,
+}
+
+impl LocalApic {
+    //every CPU sees its own local APIC at the same address
+    fn new(address: PhysicalAddress) -> LocalApic {
+        let mode = if cpuid::has_x2apic() {
+            ApicMode::X2Apic
+        } else {
+            mem::identity_map_range(address, 4096,
+                EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::NO_EXECUTE);
+            ApicMode::XApic(address)
+        };
+
+        LocalApic { mode: mode }
+    }
+
+    pub fn mode(&self) -> ApicMode {
+        self.mode
+    }
+
+    pub fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            match self.mode {
+                ApicMode::XApic(base) => ptr::read_volatile((base + reg as usize) as *const u32),
+                ApicMode::X2Apic => msr::rdmsr(0x800 + (reg >> 4)) as u32,
+            }
+        }
+    }
+
+    pub fn write(&self, reg: u32, value: u32) {
+        unsafe {
+            match self.mode {
+                ApicMode::XApic(base) => ptr::write_volatile((base + reg as usize) as *mut u32, value),
+                ApicMode::X2Apic => msr::wrmsr(0x800 + (reg >> 4), value as u64),
+            }
+        }
+    }
+
+    //APIC id of the CPU this runs on
+    pub fn id(&self) -> u32 {
+        match self.mode {
+            ApicMode::XApic(_) => self.read(REG_ID) >> 24,
+            ApicMode::X2Apic => self.read(REG_ID),
+        }
+    }
+
+    pub fn eoi(&self) {
+        self.write(REG_EOI, 0);
+    }
+
+    //whether the local APIC has a vector in service, awaiting its EOI
+    pub fn in_service(&self, vector: u8) -> bool {
+        let reg = REG_ISR + (vector as u32 / 32) * 0x10;
+        self.read(reg) & (1 << (vector % 32)) != 0
+    }
+
+    //send an inter-processor interrupt, the low half of the ICR gives the
+    //vector and delivery mode
+    pub fn send_ipi(&self, apic_id: u32, command: u32) {
+        match self.mode {
+            ApicMode::XApic(_) => {
+                //writing the low half sends the interrupt
+                self.write(REG_ICR_HIGH, apic_id << 24);
+                self.write(REG_ICR_LOW, command);
+
+                while self.read(REG_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {}
+            }
+            ApicMode::X2Apic => unsafe {
+                msr::wrmsr(0x800 + (REG_ICR_LOW >> 4), (apic_id as u64) << 32 | command as u64);
+            },
+        }
+    }
+
+    //reset a processor, it then waits for a startup IPI
+    pub fn send_init(&self, apic_id: u32) {
+        self.send_ipi(apic_id, ICR_INIT | ICR_LEVEL_ASSERT);
+    }
+
+    //start a processor in real mode at page * 4096, which must be below 1 MiB
+    pub fn send_startup(&self, apic_id: u32, page: u8) {
+        self.send_ipi(apic_id, ICR_STARTUP | ICR_LEVEL_ASSERT | page as u32);
+    }
+
+    //enable the local APIC of the calling CPU
+    pub fn enable(&self, madt: &Madt) {
+        unsafe {
+            let mut base = msr::rdmsr(msr::IA32_APIC_BASE) | APIC_BASE_ENABLE;
+            if let ApicMode::X2Apic = self.mode {
+                base |= APIC_BASE_X2APIC;
+            }
+            msr::wrmsr(msr::IA32_APIC_BASE, base);
+        }
+
+        self.write(REG_SPURIOUS, SPURIOUS_ENABLE | SPURIOUS_VECTOR as u32);
+        //accept interrupts of every priority
+        self.write(REG_TPR, 0);
+        self.write(REG_LVT_ERROR, LVT_MASKED);
+
+        //wire up the NMI lines described by the firmware
+        let id = self.id();
+        let processor_id = madt.processors.iter()
+            .find(|p| p.apic_id == id)
+            .map(|p| p.processor_id);
+
+        for nmi in madt.nmis.iter() {
+            if nmi.processor_id != 0xFF && Some(nmi.processor_id as u32) != processor_id {
+                continue;
+            }
+
+            let mut lvt = LVT_DELIVERY_NMI;
+            if nmi.polarity == Polarity::ActiveLow {
+                lvt |= LVT_ACTIVE_LOW;
+            }
+            if nmi.trigger == TriggerMode::Level {
+                lvt |= LVT_LEVEL;
+            }
+
+            match nmi.lint {
+                0 => self.write(REG_LVT_LINT0, lvt),
+                1 => self.write(REG_LVT_LINT1, lvt),
+                _ => {}
+            }
+        }
+    }
+}
+
+static LOCAL_APIC: Once<LocalApic> = Once::new();
+
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.try()
+}
+
+//routes irqs through the I/O APICs to the bootstrap processor
+pub struct ApicController {
+    io_apics: Mutex<Vec<IoApic>>,
+    madt: Madt,
+    //destination for every routed irq
+    apic_id: u32,
+}
+
+impl ApicController {
+    //global system interrupt, polarity and trigger mode of an irq line
+    fn routing(&self, irq: u8) -> (u32, Polarity, TriggerMode) {
+        if irq < 16 {
+            self.madt.isa_irq_routing(irq)
+        } else {
+            //PCI style interrupts
+            (irq as u32, Polarity::ActiveLow, TriggerMode::Level)
+        }
+    }
+}
+
+impl InterruptController for ApicController {
+    fn name(&self) -> &'static str {
+        "APIC"
+    }
+
+    fn mask(&self, irq: u8) {
+        let (gsi, _, _) = self.routing(irq);
+
+        if let Some(io_apic) = self.io_apics.lock().iter().find(|io| io.handles(gsi)) {
+            io_apic.set_masked(gsi, true);
+        }
+    }
+
+    fn unmask(&self, irq: u8) {
+        let (gsi, polarity, trigger) = self.routing(irq);
+
+        //vectors raised by the local APIC itself have no redirection entry
+        if let Some(io_apic) = self.io_apics.lock().iter().find(|io| io.handles(gsi)) {
+            io_apic.set_redirection(gsi, irq::IRQ_BASE + irq, self.apic_id, polarity, trigger, false);
+        }
+    }
+
+    fn eoi(&self, _irq: u8) {
+        if let Some(local) = local_apic() {
+            local.eoi();
+        }
+    }
+
+    fn is_spurious(&self, irq: u8) -> bool {
+        if irq == SPURIOUS_VECTOR - irq::IRQ_BASE {
+            return true;
+        }
+
+        //the masked 8259s can still raise spurious irqs 7 and 15 on their old
+        //vectors. Those arrive through LINT0, so unlike a routed irq they are
+        //not in service at the local APIC, and its EOI would acknowledge
+        //whichever interrupt is
+        if irq == 7 || irq == 15 {
+            let spurious = local_apic().map_or(false, |local| !local.in_service(irq::IRQ_BASE + irq));
+            if spurious && irq == 15 {
+                //a spurious irq 15 still reached the master through the cascade
+                pic::ack(pic::CASCADE_IRQ);
+            }
+            return spurious;
+        }
+
+        false
+    }
+
+    fn disable(&self) {
+        for io_apic in self.io_apics.lock().iter() {
+            io_apic.mask_all();
+        }
+    }
+}
+
+static CONTROLLER: Once<ApicController> = Once::new();
+
+//switch irq delivery from the 8259 PICs to the APICs, if the MADT lists them
+pub fn init() -> bool {
+    if !cpuid::has_apic() {
+        return false;
+    }
+
+    let madt = match acpi::madt() {
+        Some(madt) => madt,
+        None => return false,
+    };
+
+    if madt.io_apics.is_empty() {
+        return false;
+    }
+
+    let local = LOCAL_APIC.call_once(|| LocalApic::new(madt.local_apic_address));
+    local.enable(&madt);
+
+    let io_apics: Vec<IoApic> = madt.io_apics.iter()
+        .map(|info| IoApic::new(info))
+        .collect();
+
+    for io_apic in io_apics.iter() {
+        io_apic.mask_all();
+    }
+
+    let apic_id = local.id();
+    let controller = CONTROLLER.call_once(|| ApicController {
+        io_apics: Mutex::new(io_apics),
+        madt: madt,
+        apic_id: apic_id,
+    });
+
+    irq::set_controller(controller);
+    true
+}
+
+pub fn controller() -> Option<&'static ApicController> {
+    CONTROLLER.try()
+}
+
+impl ApicController {
+    pub fn io_apic_count(&self) -> usize {
+        self.io_apics.lock().len()
+    }
+
+    pub fn madt(&self) -> &Madt {
+        &self.madt
+    }
+}
@@ -0,0 +1,117 @@
+//hpet.rs
+//High Precision Event Timer, used as a free running counter
+
+use core::ptr;
+use spin::Once;
+use arch::x86_64::acpi;
+use arch::x86_64::acpi::hpet::HpetTable;
+use arch::x86_64::mem;
+use arch::x86_64::mem::VirtualAddress;
+use arch::x86_64::mem::entry::EntryFlags;
+use time::clocksource::ClockSource;
+
+//register offsets
+const REG_CAPABILITIES: usize = 0x000;
+const REG_CONFIG: usize = 0x010;
+const REG_MAIN_COUNTER: usize = 0x0F0;
+
+//general capabilities
+const CAP_COUNTER_64BIT: u64 = 1 << 13;
+
+//general configuration
+const CONFIG_ENABLE: u64 = 1 << 0;
+const CONFIG_LEGACY_ROUTE: u64 = 1 << 1;
+
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+//the specification caps the tick period at 100 ns
+const MAX_PERIOD: u64 = 100_000_000;
+
+pub struct Hpet {
+    base: VirtualAddress,
+    //main counter tick, in femtoseconds
+    period: u64,
+    //nanoseconds per tick as a 32.32 fixed point number
+    scale: u64,
+}
+
+impl Hpet {
+    fn new(table: &HpetTable) -> Option<Hpet> {
+        mem::identity_map_range(table.base_address, 1024,
+            EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::NO_EXECUTE);
+
+        let mut hpet = Hpet {
+            base: table.base_address,
+            period: 0,
+            scale: 0,
+        };
+
+        let capabilities = hpet.read(REG_CAPABILITIES);
+        hpet.period = capabilities >> 32;
+        if hpet.period == 0 || hpet.period > MAX_PERIOD {
+            return None;
+        }
+
+        //a 32-bit counter wraps within minutes, too soon for a clock
+        if capabilities & CAP_COUNTER_64BIT == 0 {
+            return None;
+        }
+
+        hpet.scale = (hpet.period << 32) / FEMTOS_PER_NANO;
+        Some(hpet)
+    }
+
+    fn read(&self, reg: usize) -> u64 {
+        unsafe { ptr::read_volatile((self.base + reg) as *const u64) }
+    }
+
+    fn write(&self, reg: usize, value: u64) {
+        unsafe { ptr::write_volatile((self.base + reg) as *mut u64, value) }
+    }
+
+    //start the main counter, leaving the PIT and RTC interrupts where they are
+    fn enable(&self) {
+        let config = self.read(REG_CONFIG);
+        self.write(REG_CONFIG, (config & !CONFIG_LEGACY_ROUTE) | CONFIG_ENABLE);
+    }
+
+    pub fn counter(&self) -> u64 {
+        self.read(REG_MAIN_COUNTER)
+    }
+
+    pub fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.period
+    }
+}
+
+impl ClockSource for Hpet {
+    fn name(&self) -> &'static str {
+        "HPET"
+    }
+
+    fn nanos(&self) -> u64 {
+        ((self.counter() as u128 * self.scale as u128) >> 32) as u64
+    }
+
+    fn resolution(&self) -> u64 {
+        (self.period / FEMTOS_PER_NANO).max(1)
+    }
+}
+
+static HPET: Once<Hpet> = Once::new();
+
+//find the HPET through ACPI and start its counter
+pub fn init() -> Option<&'static Hpet> {
+    if let Some(hpet) = HPET.try() {
+        return Some(hpet);
+    }
+
+    let hpet = Hpet::new(&acpi::hpet()?)?;
+    let hpet = HPET.call_once(|| hpet);
+    hpet.enable();
+
+    Some(hpet)
+}
+
+pub fn hpet() -> Option<&'static Hpet> {
+    HPET.try()
+}
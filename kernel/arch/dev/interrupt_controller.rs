@@ -0,0 +1,55 @@
+//interrupt_controller.rs
+//common interface to the interrupt controllers, so the rest of the kernel
+//doesn't care whether the 8259 PICs or the APICs are delivering irqs
+
+use arch::dev::pic;
+
+pub trait InterruptController: Sync {
+    fn name(&self) -> &'static str;
+
+    //stop an irq line from being delivered
+    fn mask(&self, irq: u8);
+
+    //route an irq line to its vector (IRQ_BASE + irq) and let it through
+    fn unmask(&self, irq: u8);
+
+    //signal end of interrupt
+    fn eoi(&self, irq: u8);
+
+    //true if the irq must be ignored, without an EOI
+    fn is_spurious(&self, irq: u8) -> bool;
+
+    //mask every line, called when another controller takes over
+    fn disable(&self);
+}
+
+//the legacy master/slave 8259 pair
+pub struct LegacyPic;
+
+pub static LEGACY_PIC: LegacyPic = LegacyPic;
+
+impl InterruptController for LegacyPic {
+    fn name(&self) -> &'static str {
+        "8259 PIC"
+    }
+
+    fn mask(&self, irq: u8) {
+        pic::irq_set_mask(irq, true);
+    }
+
+    fn unmask(&self, irq: u8) {
+        pic::irq_set_mask(irq, false);
+    }
+
+    fn eoi(&self, irq: u8) {
+        pic::ack(irq);
+    }
+
+    fn is_spurious(&self, irq: u8) -> bool {
+        pic::is_spurious(irq)
+    }
+
+    fn disable(&self) {
+        pic::disable();
+    }
+}
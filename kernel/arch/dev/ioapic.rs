@@ -0,0 +1,125 @@
+//ioapic.rs
+//I/O APIC driver, routes global system interrupts to local APIC vectors
+
+use core::ptr;
+use arch::x86_64::acpi::madt::{IoApicInfo, Polarity, TriggerMode};
+use arch::x86_64::mem;
+use arch::x86_64::mem::VirtualAddress;
+use arch::x86_64::mem::entry::EntryFlags;
+
+//register select and data window, relative to the base address
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+
+//registers
+const IOAPICID: u32 = 0x00;
+const IOAPICVER: u32 = 0x01;
+const IOREDTBL: u32 = 0x10;
+
+//redirection entry bits
+const REDIRECT_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECT_LEVEL: u64 = 1 << 15;
+const REDIRECT_MASKED: u64 = 1 << 16;
+
+pub struct IoApic {
+    id: u8,
+    base: VirtualAddress,
+    gsi_base: u32,
+    //number of redirection entries
+    entries: u32,
+}
+
+impl IoApic {
+    pub fn new(info: &IoApicInfo) -> IoApic {
+        mem::identity_map_range(info.address, 4096,
+            EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::NO_EXECUTE);
+
+        let mut io_apic = IoApic {
+            id: info.id,
+            base: info.address,
+            gsi_base: info.gsi_base,
+            entries: 0,
+        };
+
+        io_apic.entries = ((io_apic.read(IOAPICVER) >> 16) & 0xFF) + 1;
+        io_apic
+    }
+
+    fn read(&self, reg: u32) -> u32 {
+        unsafe {
+            ptr::write_volatile((self.base + IOREGSEL) as *mut u32, reg);
+            ptr::read_volatile((self.base + IOWIN) as *const u32)
+        }
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        unsafe {
+            ptr::write_volatile((self.base + IOREGSEL) as *mut u32, reg);
+            ptr::write_volatile((self.base + IOWIN) as *mut u32, value);
+        }
+    }
+
+    fn read_redirection(&self, index: u32) -> u64 {
+        let low = self.read(IOREDTBL + index * 2) as u64;
+        let high = self.read(IOREDTBL + index * 2 + 1) as u64;
+        (high << 32) | low
+    }
+
+    fn write_redirection(&self, index: u32, value: u64) {
+        //mask first, so a half-written entry never fires
+        self.write(IOREDTBL + index * 2, REDIRECT_MASKED as u32);
+        self.write(IOREDTBL + index * 2 + 1, (value >> 32) as u32);
+        self.write(IOREDTBL + index * 2, value as u32);
+    }
+
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    pub fn hardware_id(&self) -> u8 {
+        (self.read(IOAPICID) >> 24) as u8
+    }
+
+    pub fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.entries
+    }
+
+    //route a global system interrupt to a vector on the CPU with the given APIC id
+    pub fn set_redirection(&self, gsi: u32, vector: u8, apic_id: u32,
+                           polarity: Polarity, trigger: TriggerMode, masked: bool) {
+        assert!(self.handles(gsi));
+
+        //fixed delivery, physical destination
+        let mut entry = vector as u64 | ((apic_id as u64 & 0xFF) << 56);
+        if polarity == Polarity::ActiveLow {
+            entry |= REDIRECT_ACTIVE_LOW;
+        }
+        if trigger == TriggerMode::Level {
+            entry |= REDIRECT_LEVEL;
+        }
+        if masked {
+            entry |= REDIRECT_MASKED;
+        }
+
+        self.write_redirection(gsi - self.gsi_base, entry);
+    }
+
+    pub fn set_masked(&self, gsi: u32, masked: bool) {
+        assert!(self.handles(gsi));
+
+        let index = gsi - self.gsi_base;
+        let entry = self.read_redirection(index);
+
+        if masked {
+            self.write_redirection(index, entry | REDIRECT_MASKED);
+        } else {
+            self.write_redirection(index, entry & !REDIRECT_MASKED);
+        }
+    }
+
+    pub fn mask_all(&self) {
+        for index in 0..self.entries {
+            self.write_redirection(index, REDIRECT_MASKED);
+        }
+    }
+}
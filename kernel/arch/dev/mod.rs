@@ -1,10 +1,22 @@
 pub mod pic;
+pub mod interrupt_controller;
+pub mod apic;
+pub mod ioapic;
+pub mod hpet;
+pub mod rtc;
 pub mod pit;
 pub mod port_io;
 
 use driver::vga;
 use core::fmt::Write;
 use driver::vga::Writer;
+use arch::x86_64::int::irq;
+
+//legacy irq lines
+pub const PIT_IRQ: u8 = 0;
+pub const KBD_IRQ: u8 = 1;
+pub const COM1_IRQ: u8 = 4;
+pub const RTC_IRQ: u8 = 8;
 
 pub fn pic_init() {
     unsafe {
@@ -32,12 +44,10 @@ pub fn pic_init() {
         port_io::outb(pic::PIC_SLAVE_DATA, pic::ICW4_8086); //operate in 8086 mode
         port_io::wait();
 
-        //mask all interrupts, since none are currently initialised
+        //mask all interrupts, lines are unmasked as handlers are registered
         port_io::outb(pic::PIC_MASTER_DATA, 0xFF);
         port_io::outb(pic::PIC_SLAVE_DATA, 0xFF);
 
-        pic::irq_set_mask(0, false);
-        pic::irq_set_mask(1, false);
     }
 
     vga::okay();
@@ -46,7 +56,24 @@ pub fn pic_init() {
 
 pub fn pit_init(hz: u32) {
 	pit::set_phase(hz);
+	irq::register_irq(PIT_IRQ, pit::tick).expect("Could not register PIT handler");
         vga::okay();
 	write!(Writer::new(), "Initialised the PIT, at a phase of {:#} Hz\n", hz)
 		.expect("Unexpected failure in write!()");
 }
+
+//replace the 8259s with the local and I/O APICs, when ACPI describes them
+pub fn apic_init() -> bool {
+    if !apic::init() {
+        vga::info();
+        vga::println("No usable APIC, staying on the 8259 PIC");
+        return false;
+    }
+
+    let controller = apic::controller().unwrap();
+    vga::okay();
+    write!(Writer::new(), "Initialised the APIC, {} I/O APIC(s), {} processor(s)\n",
+        controller.io_apic_count(), controller.madt().processors.len())
+        .expect("Unexpected failure in write!()");
+    true
+}
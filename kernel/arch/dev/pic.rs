@@ -12,6 +12,9 @@ pub const PIC_MASTER_DATA: u16 = PIC_MASTER + 1;
 pub const PIC_SLAVE_COMMAND: u16 = PIC_SLAVE;
 pub const PIC_SLAVE_DATA: u16 = PIC_SLAVE + 1;
 
+//master line the slave PIC is cascaded on
+pub const CASCADE_IRQ: u8 = 2;
+
 //code for EOI (end of interrupt) signal
 pub const EOI: u8 = 0x20;
 
@@ -54,6 +57,24 @@ pub fn ack(irq: u8) {
     }
 }
 
+//irqs 7 and 15 can be raised spuriously, without the matching ISR bit set
+//a spurious irq 15 still reached the master through the cascade, so the master
+//is acknowledged here -- callers must not send an EOI for spurious irqs
+pub fn is_spurious(irq: u8) -> bool {
+    match irq {
+        7 => pic_get_isr() & (1 << 7) == 0,
+        15 => {
+            if pic_get_isr() & (1 << 15) == 0 {
+                ack(2);
+                true
+            } else {
+                false
+            }
+        }
+        _ => false,
+    }
+}
+
 //mask or unmask a specific irq in the PIC
 pub fn irq_set_mask(mut irq: u8, enable: bool) {
     unsafe {
@@ -74,6 +95,12 @@ pub fn irq_set_mask(mut irq: u8, enable: bool) {
         if !enable {
             value = port_io::inb(port) & !(1 << irq);
             port_io::outb(port, value);
+
+            //slave irqs only reach the CPU through the cascade line
+            if port == PIC_SLAVE_DATA {
+                value = port_io::inb(PIC_MASTER_DATA) & !(1 << CASCADE_IRQ);
+                port_io::outb(PIC_MASTER_DATA, value);
+            }
         } else {
             value = port_io::inb(port) | (1 << irq);
             port_io::outb(port, value);
@@ -81,6 +108,14 @@ pub fn irq_set_mask(mut irq: u8, enable: bool) {
     }
 }
 
+//mask every line at both PICs, used once the APICs take over
+pub fn disable() {
+    unsafe {
+        port_io::outb(PIC_MASTER_DATA, 0xFF);
+        port_io::outb(PIC_SLAVE_DATA, 0xFF);
+    }
+}
+
 fn pic_get_irq_reg(ocw3: u8) -> u16 {
     unsafe {
         port_io::outb(PIC_MASTER_COMMAND, ocw3);
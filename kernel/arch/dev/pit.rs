@@ -2,9 +2,10 @@
 //contains methods to program the Programmable Interval Timer
 
 use arch::dev::port_io;
+use task::scheduler;
+use time;
 
 pub static mut RATE: u32 = 0;
-pub static mut TICKS: u32 = 0;
 
 //port addresses
 pub const CMD: u16 = 0x43;
@@ -25,11 +26,13 @@ pub fn set_phase(hz: u32) {
         port_io::outb(DATA_0, (divisor & 0xFF) as u8); //low
         port_io::outb(DATA_0, (divisor >> 8) as u8); //high
     }
+
+    //the real period, the divisor rounds the requested rate
+    time::set_tick_period(divisor as u64 * time::NANOS_PER_SEC / MAX_RATE as u64);
 }
 
-pub fn timer_wait(ms: u32) {
-    unsafe {
-        while TICKS < ms {}
-        TICKS = 0;
-    }
+//irq 0 handler, drives the monotonic clock and preemption
+pub fn tick(_irq: u8) {
+    time::tick();
+    scheduler::timer_tick();
 }
@@ -0,0 +1,160 @@
+//rtc.rs
+//CMOS real-time clock: calendar time and the periodic interrupt on irq 8
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use arch::dev::port_io;
+use arch::dev::RTC_IRQ;
+use arch::x86_64::acpi;
+use arch::x86_64::int::int;
+use arch::x86_64::int::irq;
+use arch::x86_64::int::irq::IrqError;
+use time::system::DateTime;
+
+//port addresses
+pub const CMOS_ADDRESS: u16 = 0x70;
+pub const CMOS_DATA: u16 = 0x71;
+
+//registers
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+const REG_STATUS_C: u8 = 0x0C;
+
+//status register bits
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_PERIODIC: u8 = 1 << 6;
+
+//set in the hours register for PM in 12-hour mode
+const HOURS_PM: u8 = 0x80;
+
+//rates 3 to 15 give 32768 >> (rate - 1) interrupts per second
+const MIN_RATE: u8 = 3;
+const MAX_RATE: u8 = 15;
+
+//periodic interrupts received
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+//the caller must keep interrupts disabled, so the address/data pair is not split
+unsafe fn read_register(reg: u8) -> u8 {
+    port_io::outb(CMOS_ADDRESS, reg);
+    port_io::inb(CMOS_DATA)
+}
+
+unsafe fn write_register(reg: u8, value: u8) {
+    port_io::outb(CMOS_ADDRESS, reg);
+    port_io::outb(CMOS_DATA, value);
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+//raw registers, in the order seconds, minutes, hours, day, month, year, century
+fn read_raw(century_reg: u8) -> [u8; 7] {
+    int::without_interrupts(|| unsafe {
+        while read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {}
+
+        [
+            read_register(REG_SECONDS),
+            read_register(REG_MINUTES),
+            read_register(REG_HOURS),
+            read_register(REG_DAY),
+            read_register(REG_MONTH),
+            read_register(REG_YEAR),
+            if century_reg != 0 { read_register(century_reg) } else { 0 },
+        ]
+    })
+}
+
+//read the current date and time, none if the FADT says there is no CMOS RTC
+pub fn read_date_time() -> Option<DateTime> {
+    let fadt = acpi::fadt();
+    if fadt.as_ref().map_or(false, |fadt| !fadt.has_cmos_rtc()) {
+        return None;
+    }
+    let century_reg = fadt.map_or(0, |fadt| fadt.century);
+
+    //an update can still start between the check and the reads, so read
+    //until two passes agree
+    let mut raw = read_raw(century_reg);
+    loop {
+        let again = read_raw(century_reg);
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+
+    let status_b = int::without_interrupts(|| unsafe { read_register(REG_STATUS_B) });
+    let binary = status_b & STATUS_B_BINARY != 0;
+    let convert = |value: u8| if binary { value } else { bcd_to_binary(value) };
+
+    let mut hour = convert(raw[2] & !HOURS_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        //12 AM is midnight, 12 PM is noon
+        hour %= 12;
+        if raw[2] & HOURS_PM != 0 {
+            hour += 12;
+        }
+    }
+
+    let century = if century_reg != 0 { convert(raw[6]) as u32 } else { 20 };
+
+    Some(DateTime {
+        year: century * 100 + convert(raw[5]) as u32,
+        month: convert(raw[4]),
+        day: convert(raw[3]),
+        hour: hour,
+        minute: convert(raw[1]),
+        second: convert(raw[0]),
+    })
+}
+
+//irq 8 handler
+pub fn tick(_irq: u8) {
+    //status C must be read, or the RTC raises no further interrupts
+    unsafe { read_register(REG_STATUS_C); }
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn ticks() -> usize {
+    TICKS.load(Ordering::Relaxed)
+}
+
+//start the periodic interrupt at 32768 >> (rate - 1) Hz
+pub fn enable_periodic(rate: u8) -> Result<(), IrqError> {
+    assert!(rate >= MIN_RATE && rate <= MAX_RATE, "invalid RTC rate");
+
+    int::without_interrupts(|| unsafe {
+        let status_a = read_register(REG_STATUS_A);
+        write_register(REG_STATUS_A, (status_a & 0xF0) | rate);
+
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b | STATUS_B_PERIODIC);
+
+        //clear any interrupt that is already pending
+        read_register(REG_STATUS_C);
+    });
+
+    irq::register_irq(RTC_IRQ, tick)
+}
+
+pub fn disable_periodic() -> Result<(), IrqError> {
+    int::without_interrupts(|| unsafe {
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b & !STATUS_B_PERIODIC);
+    });
+
+    irq::unregister_irq(RTC_IRQ)
+}
+
+pub fn periodic_frequency(rate: u8) -> u32 {
+    32768 >> (rate - 1)
+}
@@ -1,2 +1,3 @@
+#[macro_use]
 pub mod x86_64;
 pub mod dev;
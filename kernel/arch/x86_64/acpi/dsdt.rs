@@ -0,0 +1,75 @@
+// dsdt.rs
+// Differentiated System Description Table, and the SSDTs that extend it.
+// Full AML interpretation is not supported, only simple named packages such
+// as the sleep state objects.
+
+use core::slice;
+use arch::x86_64::acpi::sdt::SdtHeader;
+
+// AML opcodes
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const NAME_OP: u8 = 0x08;
+const BYTE_PREFIX: u8 = 0x0A;
+const WORD_PREFIX: u8 = 0x0B;
+const PACKAGE_OP: u8 = 0x12;
+const ROOT_CHAR: u8 = b'\\';
+
+// Read an integer package element, returning its value and the offset of
+// the next element. Anything else, such as a method call, cannot be
+// evaluated here
+fn read_integer(aml: &[u8], offset: usize) -> Option<(u8, usize)> {
+    match *aml.get(offset)? {
+        ZERO_OP => Some((0, offset + 1)),
+        ONE_OP => Some((1, offset + 1)),
+        BYTE_PREFIX => aml.get(offset + 1).map(|&value| (value, offset + 2)),
+        WORD_PREFIX => aml.get(offset + 1).map(|&value| (value, offset + 3)),
+        _ => None,
+    }
+}
+
+// SLP_TYPa and SLP_TYPb values of a sleep state object, eg. b"_S5_" for soft
+// off, defined in the given DSDT or SSDT
+pub fn sleep_type(table: &'static SdtHeader, object: &[u8; 4]) -> Option<(u8, u8)> {
+    let aml = unsafe {
+        slice::from_raw_parts(table.data_address() as *const u8, table.data_length())
+    };
+
+    // The name may also be referenced before it is defined
+    aml.windows(4).enumerate()
+        .filter(|&(_, name)| name == object)
+        .filter_map(|(position, _)| read_sleep_package(aml, position))
+        .next()
+}
+
+// The two integers at the start of the package named at position
+fn read_sleep_package(aml: &[u8], position: usize) -> Option<(u8, u8)> {
+    // Must be a Name(_Sx, Package() {...}) definition, possibly rooted
+    let defined = match (position.checked_sub(1).map(|i| aml[i]),
+                         position.checked_sub(2).map(|i| aml[i])) {
+        (Some(NAME_OP), _) => true,
+        (Some(ROOT_CHAR), Some(NAME_OP)) => true,
+        _ => false,
+    };
+    if !defined {
+        return None;
+    }
+
+    let mut offset = position + 4;
+    if *aml.get(offset)? != PACKAGE_OP {
+        return None;
+    }
+    offset += 1;
+
+    // PkgLength: bits 6-7 of the lead byte count the bytes that follow it
+    let length_bytes = (*aml.get(offset)? >> 6) as usize;
+    offset += length_bytes + 1;
+
+    // NumElements
+    offset += 1;
+
+    let (slp_typa, offset) = read_integer(aml, offset)?;
+    let (slp_typb, _) = read_integer(aml, offset)?;
+
+    Some((slp_typa, slp_typb))
+}
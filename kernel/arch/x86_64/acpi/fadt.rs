@@ -0,0 +1,143 @@
+// fadt.rs
+// Fixed ACPI Description Table: power management registers, the DSDT and
+// a few legacy hardware flags
+
+use arch::x86_64::mem::PhysicalAddress;
+use arch::x86_64::acpi::sdt::{SdtHeader, GenericAddress, read};
+
+// Offsets of the fields used, from the start of the table
+const OFFSET_DSDT: usize = 40;
+const OFFSET_SCI_INTERRUPT: usize = 46;
+const OFFSET_SMI_COMMAND: usize = 48;
+const OFFSET_ACPI_ENABLE: usize = 52;
+const OFFSET_ACPI_DISABLE: usize = 53;
+const OFFSET_PM1A_EVENT: usize = 56;
+const OFFSET_PM1B_EVENT: usize = 60;
+const OFFSET_PM1A_CONTROL: usize = 64;
+const OFFSET_PM1B_CONTROL: usize = 68;
+const OFFSET_PM1_EVENT_LENGTH: usize = 88;
+const OFFSET_PM1_CONTROL_LENGTH: usize = 89;
+const OFFSET_CENTURY: usize = 108;
+const OFFSET_BOOT_ARCH: usize = 109;
+const OFFSET_FLAGS: usize = 112;
+const OFFSET_RESET_REGISTER: usize = 116;
+const OFFSET_RESET_VALUE: usize = 128;
+const OFFSET_X_DSDT: usize = 140;
+const OFFSET_X_PM1A_CONTROL: usize = 172;
+const OFFSET_X_PM1B_CONTROL: usize = 184;
+
+// IA-PC boot architecture flags
+const BOOT_ARCH_8042: u16 = 1 << 1;
+const BOOT_ARCH_NO_CMOS_RTC: u16 = 1 << 5;
+
+// Fixed feature flags
+const FLAG_RESET_REGISTER_SUPPORTED: u32 = 1 << 10;
+const FLAG_HW_REDUCED_ACPI: u32 = 1 << 20;
+
+#[derive(Debug)]
+pub struct Fadt {
+    pub revision: u8,
+    pub dsdt: PhysicalAddress,
+    pub sci_interrupt: u16,
+    // Port written with acpi_enable/acpi_disable to switch ACPI mode, 0 if
+    // the system is always in ACPI mode
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    // I/O ports of the PM1 event and control blocks, 0 if not present
+    pub pm1a_event: u32,
+    pub pm1b_event: u32,
+    pub pm1a_control: u32,
+    pub pm1b_control: u32,
+    pub pm1_event_length: u8,
+    pub pm1_control_length: u8,
+    // CMOS index of the RTC century register, 0 if not supported
+    pub century: u8,
+    pub boot_arch: u16,
+    pub flags: u32,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    pub fn parse(header: &'static SdtHeader) -> Fadt {
+        let table = header as *const _ as usize;
+        let length = header.length();
+
+        // Fields past the end of an older, shorter table read as zero
+        let field = |offset: usize, size: usize| offset + size <= length;
+
+        unsafe {
+            let mut fadt = Fadt {
+                revision: header.revision(),
+                dsdt: read::<u32>(table + OFFSET_DSDT) as PhysicalAddress,
+                sci_interrupt: read::<u16>(table + OFFSET_SCI_INTERRUPT),
+                smi_command: read::<u32>(table + OFFSET_SMI_COMMAND),
+                acpi_enable: read::<u8>(table + OFFSET_ACPI_ENABLE),
+                acpi_disable: read::<u8>(table + OFFSET_ACPI_DISABLE),
+                pm1a_event: read::<u32>(table + OFFSET_PM1A_EVENT),
+                pm1b_event: read::<u32>(table + OFFSET_PM1B_EVENT),
+                pm1a_control: read::<u32>(table + OFFSET_PM1A_CONTROL),
+                pm1b_control: read::<u32>(table + OFFSET_PM1B_CONTROL),
+                pm1_event_length: read::<u8>(table + OFFSET_PM1_EVENT_LENGTH),
+                pm1_control_length: read::<u8>(table + OFFSET_PM1_CONTROL_LENGTH),
+                century: 0,
+                boot_arch: 0,
+                flags: 0,
+                reset_register: None,
+                reset_value: 0,
+            };
+
+            if field(OFFSET_FLAGS, 4) {
+                fadt.century = read::<u8>(table + OFFSET_CENTURY);
+                fadt.boot_arch = read::<u16>(table + OFFSET_BOOT_ARCH);
+                fadt.flags = read::<u32>(table + OFFSET_FLAGS);
+            }
+
+            if field(OFFSET_RESET_VALUE, 1) && fadt.flags & FLAG_RESET_REGISTER_SUPPORTED != 0 {
+                let register = read::<GenericAddress>(table + OFFSET_RESET_REGISTER);
+                if !register.is_null() {
+                    fadt.reset_register = Some(register);
+                    fadt.reset_value = read::<u8>(table + OFFSET_RESET_VALUE);
+                }
+            }
+
+            // ACPI 2.0+ 64-bit addresses take precedence when present
+            if field(OFFSET_X_DSDT, 8) {
+                let x_dsdt = read::<u64>(table + OFFSET_X_DSDT);
+                if x_dsdt != 0 {
+                    fadt.dsdt = x_dsdt as PhysicalAddress;
+                }
+            }
+
+            if field(OFFSET_X_PM1B_CONTROL, 12) {
+                let x_pm1a = read::<GenericAddress>(table + OFFSET_X_PM1A_CONTROL);
+                let x_pm1b = read::<GenericAddress>(table + OFFSET_X_PM1B_CONTROL);
+
+                if !x_pm1a.is_null() && x_pm1a.is_io() {
+                    fadt.pm1a_control = x_pm1a.address as u32;
+                }
+                if !x_pm1b.is_null() && x_pm1b.is_io() {
+                    fadt.pm1b_control = x_pm1b.address as u32;
+                }
+            }
+
+            fadt
+        }
+    }
+
+    // The PS/2 controller exists, so it can be used to reset the machine
+    pub fn has_8042(&self) -> bool {
+        // ACPI 1.0 tables have no boot architecture flags, assume a PC
+        self.revision < 2 || self.boot_arch & BOOT_ARCH_8042 != 0
+    }
+
+    pub fn has_cmos_rtc(&self) -> bool {
+        self.boot_arch & BOOT_ARCH_NO_CMOS_RTC == 0
+    }
+
+    // Hardware-reduced platforms have no PM1 blocks or SCI
+    pub fn is_hardware_reduced(&self) -> bool {
+        self.flags & FLAG_HW_REDUCED_ACPI != 0
+    }
+}
@@ -0,0 +1,57 @@
+// hpet.rs
+// High Precision Event Timer description table
+
+use arch::x86_64::mem::PhysicalAddress;
+use arch::x86_64::acpi::sdt::{SdtHeader, GenericAddress, read};
+
+#[derive(Debug)]
+pub struct HpetTable {
+    // Copy of the general capabilities register, bits 0-31
+    pub hardware_id: u32,
+    pub base_address: PhysicalAddress,
+    pub number: u8,
+    // Minimum periodic tick, in main counter ticks
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+impl HpetTable {
+    pub fn parse(header: &'static SdtHeader) -> Option<HpetTable> {
+        let body = header.data_address();
+
+        if header.data_length() < 20 {
+            return None;
+        }
+
+        unsafe {
+            let base = read::<GenericAddress>(body + 4);
+            if !base.is_memory() || base.is_null() {
+                return None;
+            }
+
+            Some(HpetTable {
+                hardware_id: read::<u32>(body),
+                base_address: base.address as PhysicalAddress,
+                number: read::<u8>(body + 16),
+                minimum_tick: read::<u16>(body + 17),
+                page_protection: read::<u8>(body + 19),
+            })
+        }
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        (self.hardware_id >> 16) as u16
+    }
+
+    pub fn comparator_count(&self) -> u8 {
+        ((self.hardware_id >> 8) & 0x1F) as u8 + 1
+    }
+
+    pub fn counter_is_64bit(&self) -> bool {
+        self.hardware_id & (1 << 13) != 0
+    }
+
+    pub fn legacy_replacement_capable(&self) -> bool {
+        self.hardware_id & (1 << 15) != 0
+    }
+}
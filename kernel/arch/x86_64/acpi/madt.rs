@@ -0,0 +1,185 @@
+// madt.rs
+// Multiple APIC Description Table: processors, I/O APICs and interrupt routing
+
+use alloc::vec::Vec;
+use arch::x86_64::mem::PhysicalAddress;
+use arch::x86_64::acpi::sdt::{SdtHeader, read};
+
+// Entry types
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_NMI: u8 = 4;
+const ENTRY_LOCAL_APIC_ADDRESS: u8 = 5;
+const ENTRY_LOCAL_X2APIC: u8 = 9;
+
+// MADT flags: the system also has dual 8259 PICs
+const PCAT_COMPAT: u32 = 1 << 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    pub processor_id: u32,
+    pub apic_id: u32,
+    // Usable now, or can be brought online later
+    pub enabled: bool,
+    pub online_capable: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: PhysicalAddress,
+    // First global system interrupt handled by this I/O APIC
+    pub gsi_base: u32,
+}
+
+// A legacy ISA irq that is not identity mapped to the same GSI, or that has
+// a non-standard polarity or trigger mode
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub bus: u8,
+    pub source: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApicNmi {
+    // 0xFF means all processors
+    pub processor_id: u8,
+    pub lint: u8,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+#[derive(Debug)]
+pub struct Madt {
+    pub local_apic_address: PhysicalAddress,
+    pub flags: u32,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub overrides: Vec<InterruptOverride>,
+    pub nmis: Vec<LocalApicNmi>,
+}
+
+// MPS INTI flags, the bus defaults are those of ISA: active high, edge triggered
+fn decode_inti_flags(flags: u16) -> (Polarity, TriggerMode) {
+    let polarity = match flags & 0b11 {
+        0b11 => Polarity::ActiveLow,
+        _ => Polarity::ActiveHigh,
+    };
+    let trigger = match (flags >> 2) & 0b11 {
+        0b11 => TriggerMode::Level,
+        _ => TriggerMode::Edge,
+    };
+
+    (polarity, trigger)
+}
+
+impl Madt {
+    pub fn parse(header: &'static SdtHeader) -> Madt {
+        let body = header.data_address();
+        let end = body + header.data_length();
+
+        let mut madt = Madt {
+            local_apic_address: unsafe { read::<u32>(body) } as PhysicalAddress,
+            flags: unsafe { read::<u32>(body + 4) },
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+            nmis: Vec::new(),
+        };
+
+        let mut entry = body + 8;
+        while entry + 2 <= end {
+            let (kind, length) = unsafe { (read::<u8>(entry), read::<u8>(entry + 1) as usize) };
+            if length < 2 {
+                // Malformed table, stop rather than loop forever
+                break;
+            }
+
+            unsafe {
+                match kind {
+                    ENTRY_LOCAL_APIC => {
+                        let flags = read::<u32>(entry + 4);
+                        madt.processors.push(Processor {
+                            processor_id: read::<u8>(entry + 2) as u32,
+                            apic_id: read::<u8>(entry + 3) as u32,
+                            enabled: flags & 1 != 0,
+                            online_capable: flags & 2 != 0,
+                        });
+                    }
+                    ENTRY_IO_APIC => {
+                        madt.io_apics.push(IoApicInfo {
+                            id: read::<u8>(entry + 2),
+                            address: read::<u32>(entry + 4) as PhysicalAddress,
+                            gsi_base: read::<u32>(entry + 8),
+                        });
+                    }
+                    ENTRY_INTERRUPT_OVERRIDE => {
+                        let (polarity, trigger) = decode_inti_flags(read::<u16>(entry + 8));
+                        madt.overrides.push(InterruptOverride {
+                            bus: read::<u8>(entry + 2),
+                            source: read::<u8>(entry + 3),
+                            gsi: read::<u32>(entry + 4),
+                            polarity: polarity,
+                            trigger: trigger,
+                        });
+                    }
+                    ENTRY_LOCAL_APIC_NMI => {
+                        let (polarity, trigger) = decode_inti_flags(read::<u16>(entry + 3));
+                        madt.nmis.push(LocalApicNmi {
+                            processor_id: read::<u8>(entry + 2),
+                            lint: read::<u8>(entry + 5),
+                            polarity: polarity,
+                            trigger: trigger,
+                        });
+                    }
+                    ENTRY_LOCAL_APIC_ADDRESS => {
+                        madt.local_apic_address = read::<u64>(entry + 4) as PhysicalAddress;
+                    }
+                    ENTRY_LOCAL_X2APIC => {
+                        let flags = read::<u32>(entry + 8);
+                        madt.processors.push(Processor {
+                            processor_id: read::<u32>(entry + 12),
+                            apic_id: read::<u32>(entry + 4),
+                            enabled: flags & 1 != 0,
+                            online_capable: flags & 2 != 0,
+                        });
+                    }
+                    // Other entry types are not used yet
+                    _ => {}
+                }
+            }
+
+            entry += length;
+        }
+
+        madt
+    }
+
+    pub fn has_legacy_pics(&self) -> bool {
+        self.flags & PCAT_COMPAT != 0
+    }
+
+    // Global system interrupt, polarity and trigger mode of a legacy ISA irq
+    pub fn isa_irq_routing(&self, irq: u8) -> (u32, Polarity, TriggerMode) {
+        match self.overrides.iter().find(|o| o.bus == 0 && o.source == irq) {
+            Some(o) => (o.gsi, o.polarity, o.trigger),
+            None => (irq as u32, Polarity::ActiveHigh, TriggerMode::Edge),
+        }
+    }
+}
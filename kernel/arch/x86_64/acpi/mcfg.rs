@@ -0,0 +1,68 @@
+// mcfg.rs
+// PCI Express memory mapped configuration space (ECAM) description table
+
+use alloc::vec::Vec;
+use arch::x86_64::mem::PhysicalAddress;
+use arch::x86_64::acpi::sdt::{SdtHeader, read};
+
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct McfgEntry {
+    pub base_address: PhysicalAddress,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl McfgEntry {
+    // Physical address of the 4 KiB configuration space of a function
+    pub fn config_address(&self, bus: u8, device: u8, function: u8) -> Option<PhysicalAddress> {
+        if bus < self.start_bus || bus > self.end_bus || device >= 32 || function >= 8 {
+            return None;
+        }
+
+        Some(self.base_address
+            + (((bus - self.start_bus) as usize) << 20)
+            + ((device as usize) << 15)
+            + ((function as usize) << 12))
+    }
+}
+
+#[derive(Debug)]
+pub struct Mcfg {
+    pub entries: Vec<McfgEntry>,
+}
+
+impl Mcfg {
+    pub fn parse(header: &'static SdtHeader) -> Mcfg {
+        // 8 reserved bytes precede the allocation entries
+        let start = header.data_address() + 8;
+        let end = header.data_address() + header.data_length();
+
+        let mut entries = Vec::new();
+        let mut entry = start;
+        while entry + ENTRY_SIZE <= end {
+            unsafe {
+                entries.push(McfgEntry {
+                    base_address: read::<u64>(entry) as PhysicalAddress,
+                    segment: read::<u16>(entry + 8),
+                    start_bus: read::<u8>(entry + 10),
+                    end_bus: read::<u8>(entry + 11),
+                });
+            }
+            entry += ENTRY_SIZE;
+        }
+
+        Mcfg { entries: entries }
+    }
+
+    // Configuration space address of a function in any segment
+    pub fn config_address(&self, segment: u16, bus: u8, device: u8, function: u8)
+        -> Option<PhysicalAddress> {
+        self.entries.iter()
+            .filter(|entry| entry.segment == segment)
+            .filter_map(|entry| entry.config_address(bus, device, function))
+            .next()
+    }
+}
@@ -0,0 +1,188 @@
+// acpi/mod.rs
+// Discovery of the ACPI tables that describe the platform hardware
+
+pub mod rsdp;
+pub mod sdt;
+pub mod madt;
+pub mod fadt;
+pub mod hpet;
+pub mod mcfg;
+pub mod dsdt;
+
+use core::fmt::Write;
+use core::ptr;
+use core::str;
+use alloc::vec::Vec;
+use spin::Once;
+use driver::vga;
+use driver::vga::Writer;
+use arch::x86_64::mem::PhysicalAddress;
+use arch::x86_64::acpi::rsdp::Rsdp;
+use arch::x86_64::acpi::sdt::SdtHeader;
+use arch::x86_64::acpi::madt::Madt;
+use arch::x86_64::acpi::fadt::Fadt;
+use arch::x86_64::acpi::hpet::HpetTable;
+use arch::x86_64::acpi::mcfg::Mcfg;
+
+pub struct Acpi {
+    pub revision: u8,
+    // Physical addresses of every valid table listed in the RSDT or XSDT
+    tables: Vec<PhysicalAddress>,
+}
+
+impl Acpi {
+    fn from_rsdp(rsdp: &Rsdp) -> Option<Acpi> {
+        let mut tables = Vec::new();
+
+        // ACPI 2.0+ provides the XSDT, with 64-bit table pointers
+        let (root, entry_size) = if rsdp.revision() >= 2 && rsdp.xsdt_address() != 0 {
+            (unsafe { sdt::map_sdt(rsdp.xsdt_address()) }, 8)
+        } else {
+            (unsafe { sdt::map_sdt(rsdp.rsdt_address()) }, 4)
+        };
+
+        if !root.is_valid() {
+            vga::error();
+            vga::println("ACPI root table has a bad checksum");
+            return None;
+        }
+
+        let entries = root.data_address();
+        let count = root.data_length() / entry_size;
+
+        for i in 0..count {
+            let address = unsafe {
+                if entry_size == 8 {
+                    ptr::read_unaligned((entries + i * 8) as *const u64) as usize
+                } else {
+                    ptr::read_unaligned((entries + i * 4) as *const u32) as usize
+                }
+            };
+
+            // Tables with a bad checksum are left out, as if they were absent
+            let table = unsafe { sdt::map_sdt(address) };
+            if table.is_valid() {
+                tables.push(address);
+            } else {
+                let signature = table.signature();
+                vga::error();
+                write!(Writer::new(), "ACPI table {} has a bad checksum\n",
+                    str::from_utf8(&signature).unwrap_or("????"))
+                    .expect("Unexpected failure in write!()");
+            }
+        }
+
+        Some(Acpi {
+            revision: rsdp.revision(),
+            tables: tables,
+        })
+    }
+
+    // Find the first table with the given signature, eg. b"APIC" for the MADT
+    pub fn find_table(&self, signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+        self.tables.iter()
+            .map(|&address| unsafe { sdt::map_sdt(address) })
+            .find(|table| &table.signature() == signature)
+    }
+
+    pub fn tables<'a>(&'a self) -> impl Iterator<Item = &'static SdtHeader> + 'a {
+        self.tables.iter().map(|&address| unsafe { sdt::map_sdt(address) })
+    }
+}
+
+static ACPI: Once<Acpi> = Once::new();
+
+// Locate the RSDP, through the Multiboot2 information or else the BIOS area,
+// and read the root table. Returns false if the firmware provides no usable
+// ACPI tables.
+pub fn init(multiboot_address: usize) -> bool {
+    let rsdp = match rsdp::search_multiboot(multiboot_address)
+        .or_else(rsdp::search_bios_area) {
+        Some(rsdp) => rsdp,
+        None => {
+            vga::error();
+            vga::println("No ACPI tables found");
+            return false;
+        }
+    };
+
+    let acpi = match Acpi::from_rsdp(rsdp) {
+        Some(acpi) => ACPI.call_once(|| acpi),
+        None => return false,
+    };
+
+    vga::okay();
+    write!(Writer::new(), "Found ACPI {} tables:",
+        if acpi.revision >= 2 { "2.0+" } else { "1.0" })
+        .expect("Unexpected failure in write!()");
+    for table in acpi.tables() {
+        let signature = table.signature();
+        write!(Writer::new(), " {}", str::from_utf8(&signature).unwrap_or("????"))
+            .expect("Unexpected failure in write!()");
+    }
+    vga::println("");
+
+    true
+}
+
+pub fn acpi() -> Option<&'static Acpi> {
+    ACPI.try()
+}
+
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    acpi().and_then(|acpi| acpi.find_table(signature))
+}
+
+// Multiple APIC Description Table
+pub fn madt() -> Option<Madt> {
+    find_table(b"APIC").map(Madt::parse)
+}
+
+// Fixed ACPI Description Table
+pub fn fadt() -> Option<Fadt> {
+    find_table(b"FACP").map(Fadt::parse)
+}
+
+// Differentiated System Description Table, pointed to by the FADT
+pub fn dsdt() -> Option<&'static SdtHeader> {
+    let fadt = fadt()?;
+    if fadt.dsdt == 0 {
+        return None;
+    }
+
+    let dsdt = unsafe { sdt::map_sdt(fadt.dsdt) };
+    if dsdt.is_valid() { Some(dsdt) } else { None }
+}
+
+// Secondary System Description Tables, which add AML to the DSDT's
+pub fn ssdts() -> impl Iterator<Item = &'static SdtHeader> {
+    acpi().into_iter()
+        .flat_map(|acpi| acpi.tables())
+        .filter(|table| &table.signature() == b"SSDT")
+}
+
+// SLP_TYPa and SLP_TYPb values of a sleep state object, from the DSDT or
+// any of the SSDTs
+pub fn sleep_type(object: &[u8; 4]) -> Option<(u8, u8)> {
+    dsdt().into_iter()
+        .chain(ssdts())
+        .filter_map(|table| dsdt::sleep_type(table, object))
+        .next()
+}
+
+// High Precision Event Timer table
+pub fn hpet() -> Option<HpetTable> {
+    find_table(b"HPET").and_then(HpetTable::parse)
+}
+
+// PCI Express memory mapped configuration table
+pub fn mcfg() -> Option<Mcfg> {
+    find_table(b"MCFG").map(Mcfg::parse)
+}
+
+// Sum of all bytes in a table, which must be zero for the table to be valid
+pub fn checksum(address: usize, length: usize) -> u8 {
+    (0..length).fold(0u8, |sum, i| {
+        sum.wrapping_add(unsafe { ptr::read_volatile((address + i) as *const u8) })
+    })
+}
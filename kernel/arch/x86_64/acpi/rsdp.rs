@@ -0,0 +1,109 @@
+// rsdp.rs
+// Root System Description Pointer, the entry point to the ACPI tables
+
+use arch::x86_64::acpi::checksum;
+use arch::x86_64::mem;
+use arch::x86_64::mem::PhysicalAddress;
+use arch::x86_64::mem::entry::EntryFlags;
+
+const SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+// Read-only BIOS area that may contain the RSDP
+const BIOS_AREA_START: PhysicalAddress = 0xE0000;
+const BIOS_AREA_END: PhysicalAddress = 0x100000;
+
+// Multiboot2 tags holding a copy of the RSDP
+const MULTIBOOT_TAG_END: u32 = 0;
+const MULTIBOOT_TAG_ACPI_OLD: u32 = 14;
+const MULTIBOOT_TAG_ACPI_NEW: u32 = 15;
+
+// Size of the ACPI 1.0 part of the structure
+const RSDP_V1_LENGTH: usize = 20;
+
+#[repr(C, packed)]
+pub struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // ACPI 2.0+ fields
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+impl Rsdp {
+    pub fn is_valid(&self) -> bool {
+        if &self.signature != SIGNATURE {
+            return false;
+        }
+
+        let address = self as *const _ as usize;
+        if checksum(address, RSDP_V1_LENGTH) != 0 {
+            return false;
+        }
+
+        self.revision < 2 || checksum(address, self.length as usize) == 0
+    }
+
+    // 0 for ACPI 1.0, 2 for ACPI 2.0 and later
+    pub fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    pub fn oem_id(&self) -> [u8; 6] {
+        self.oem_id
+    }
+
+    pub fn rsdt_address(&self) -> PhysicalAddress {
+        self.rsdt_address as PhysicalAddress
+    }
+
+    pub fn xsdt_address(&self) -> PhysicalAddress {
+        if self.revision >= 2 { self.xsdt_address as PhysicalAddress } else { 0 }
+    }
+}
+
+// Look for the copy of the RSDP that the bootloader places in the Multiboot2
+// information, preferring the ACPI 2.0+ one. The information structure must
+// already be mapped.
+pub fn search_multiboot(info_address: usize) -> Option<&'static Rsdp> {
+    let total_size = unsafe { *(info_address as *const u32) } as usize;
+    let end = info_address + total_size;
+    let mut old = None;
+
+    // Tags follow the 8 byte fixed part, each aligned to 8 bytes
+    let mut tag = info_address + 8;
+    while tag + 8 <= end {
+        let (kind, size) = unsafe {
+            (*(tag as *const u32), *((tag + 4) as *const u32) as usize)
+        };
+
+        let rsdp = unsafe { &*((tag + 8) as *const Rsdp) };
+        match kind {
+            MULTIBOOT_TAG_END => break,
+            MULTIBOOT_TAG_ACPI_NEW if rsdp.is_valid() => return Some(rsdp),
+            MULTIBOOT_TAG_ACPI_OLD if rsdp.is_valid() => old = Some(rsdp),
+            _ => {}
+        }
+
+        if size < 8 {
+            break;
+        }
+        tag += (size + 7) & !7;
+    }
+
+    old
+}
+
+// Scan the BIOS read-only area for the RSDP, which sits on a 16 byte boundary
+pub fn search_bios_area() -> Option<&'static Rsdp> {
+    mem::identity_map_range(BIOS_AREA_START, BIOS_AREA_END - BIOS_AREA_START,
+        EntryFlags::PRESENT | EntryFlags::NO_EXECUTE);
+
+    (BIOS_AREA_START..BIOS_AREA_END).step_by(16)
+        .map(|address| unsafe { &*(address as *const Rsdp) })
+        .find(|rsdp| rsdp.is_valid())
+}
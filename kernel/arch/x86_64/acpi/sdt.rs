@@ -0,0 +1,101 @@
+// sdt.rs
+// Common header of every ACPI System Description Table
+
+use core::ptr;
+use core::mem::size_of;
+use arch::x86_64::mem;
+use arch::x86_64::mem::PhysicalAddress;
+use arch::x86_64::mem::entry::EntryFlags;
+use arch::x86_64::acpi::checksum;
+
+// Address spaces of a Generic Address Structure
+pub const ADDRESS_SPACE_MEMORY: u8 = 0;
+pub const ADDRESS_SPACE_IO: u8 = 1;
+
+#[repr(C, packed)]
+pub struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+impl SdtHeader {
+    pub fn signature(&self) -> [u8; 4] {
+        self.signature
+    }
+
+    // Length of the whole table, header included
+    pub fn length(&self) -> usize {
+        self.length as usize
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.revision
+    }
+
+    pub fn oem_id(&self) -> [u8; 6] {
+        self.oem_id
+    }
+
+    // All bytes of the table, header included, must sum to zero
+    pub fn is_valid(&self) -> bool {
+        checksum(self as *const _ as usize, self.length()) == 0
+    }
+
+    // Address of the table body, right after the header
+    pub fn data_address(&self) -> usize {
+        self as *const _ as usize + size_of::<SdtHeader>()
+    }
+
+    pub fn data_length(&self) -> usize {
+        self.length() - size_of::<SdtHeader>()
+    }
+}
+
+// Generic Address Structure, used by the FADT and HPET tables to describe
+// registers in memory or I/O space
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub fn is_null(&self) -> bool {
+        self.address == 0
+    }
+
+    pub fn is_io(&self) -> bool {
+        self.address_space == ADDRESS_SPACE_IO
+    }
+
+    pub fn is_memory(&self) -> bool {
+        self.address_space == ADDRESS_SPACE_MEMORY
+    }
+}
+
+// Read a field that may not be naturally aligned
+pub unsafe fn read<T>(address: usize) -> T {
+    ptr::read_unaligned(address as *const T)
+}
+
+// Map a table read-only, first its header to learn the length, then the rest
+pub unsafe fn map_sdt(address: PhysicalAddress) -> &'static SdtHeader {
+    let flags = EntryFlags::PRESENT | EntryFlags::NO_EXECUTE;
+
+    mem::identity_map_range(address, size_of::<SdtHeader>(), flags);
+    let header = &*(address as *const SdtHeader);
+    mem::identity_map_range(address, header.length(), flags);
+
+    header
+}
@@ -5,6 +5,8 @@
 
 ; define constants
 global start          ; global access to kernel entry
+global stack_bottom   ; boot stack bounds, so the kernel can guard the stack
+global stack_top
 extern long_mode_start
 
 section .text
@@ -162,12 +164,12 @@ p3_table:
 p2_table:
   resb 4096
 stack_bottom:
-  resb 32768    ; reserve 16 KB of stack space for kernel
+  resb 32768    ; reserve 32 KB of stack space for kernel
 stack_top:
 
 ; set up a gdt table (64-bit)
 ; this is a temporary table, before we jump to kernel
-section .read_only:
+section .rodata
 gdt64:
     dq 0 ; zero entry
 .code: equ $ - gdt64 ; store gdt offset
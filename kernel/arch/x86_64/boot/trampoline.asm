@@ -0,0 +1,100 @@
+; trampoline.asm
+
+; entry code for the application processors
+; the image between trampoline_start and trampoline_end is copied below 1 MiB
+; by smp.rs, since a started AP begins in real mode at a page aligned address.
+; it goes straight from real mode to long mode, using the page tables,
+; stack and entry point that smp.rs writes into the data fields at the end
+
+global trampoline_start
+global trampoline_end
+global trampoline_cr3
+global trampoline_stack
+global trampoline_entry
+global trampoline_cpu
+
+; must match TRAMPOLINE in smp.rs
+TRAMPOLINE_BASE equ 0x8000
+
+; address of a label once the image has been copied
+%define ADDR(label) (TRAMPOLINE_BASE + (label - trampoline_start))
+
+; only ever executed from the copy
+section .rodata
+bits 16
+align 16
+trampoline_start:
+  cli
+  cld
+
+  xor ax, ax
+  mov ds, ax
+  mov es, ax
+  mov ss, ax
+
+  lgdt [ADDR(trampoline_gdt_pointer)]
+
+  ; enable PAE
+  mov eax, cr4
+  or eax, 1 << 5
+  mov cr4, eax
+
+  ; load the kernel's P4 table, which must sit below 4 GiB
+  mov eax, [ADDR(trampoline_cr3)]
+  mov cr3, eax
+
+  ; enable long mode and the no-execute bit in EFER
+  mov ecx, 0xC0000080
+  rdmsr
+  or eax, (1 << 8) | (1 << 11)
+  wrmsr
+
+  ; enable protection, write protection and paging at once
+  mov eax, cr0
+  or eax, (1 << 31) | (1 << 16) | (1 << 0)
+  mov cr0, eax
+
+  ; load the 64-bit code segment
+  jmp dword 0x08:ADDR(trampoline_long_mode)
+
+bits 64
+trampoline_long_mode:
+  mov ax, 0x10
+  mov ds, ax
+  mov es, ax
+  mov ss, ax
+  xor ax, ax
+  mov fs, ax
+  mov gs, ax
+
+  mov rsp, [ADDR(trampoline_stack)]
+  mov rdi, [ADDR(trampoline_cpu)]
+  mov rax, [ADDR(trampoline_entry)]
+  call rax
+
+.halt:
+  hlt
+  jmp .halt
+
+; null, 64-bit code and data descriptors, as in the kernel's GDT
+align 8
+trampoline_gdt:
+  dq 0
+  dq 0x00AF9A000000FFFF
+  dq 0x00CF92000000FFFF
+
+trampoline_gdt_pointer:
+  dw trampoline_gdt_pointer - trampoline_gdt - 1
+  dd ADDR(trampoline_gdt)
+
+; filled in by smp.rs before each AP is started
+align 8
+trampoline_cr3:
+  dq 0
+trampoline_stack:
+  dq 0
+trampoline_entry:
+  dq 0
+trampoline_cpu:
+  dq 0
+trampoline_end:
@@ -0,0 +1,44 @@
+// control.rs
+// Access to the CPU control registers
+
+// CR0 bits
+pub const CR0_WRITE_PROTECT: u64 = 1 << 16; // honour read-only pages in ring 0
+
+#[inline(always)]
+pub fn read_cr0() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mov %cr0, $0" : "=r"(value));
+    }
+    value
+}
+
+#[inline(always)]
+pub unsafe fn write_cr0(value: u64) {
+    asm!("mov $0, %cr0" :: "r"(value) : "memory" : "volatile");
+}
+
+// CR2 holds the linear address that caused the last page fault
+#[inline(always)]
+pub fn read_cr2() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mov %cr2, $0" : "=r"(value));
+    }
+    value
+}
+
+// CR3 holds the physical address of the active P4 table
+#[inline(always)]
+pub fn read_cr3() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mov %cr3, $0" : "=r"(value));
+    }
+    value
+}
+
+#[inline(always)]
+pub unsafe fn write_cr3(value: u64) {
+    asm!("mov $0, %cr3" :: "r"(value) : "memory" : "volatile");
+}
@@ -0,0 +1,43 @@
+// cpuid.rs
+// Processor identification and feature flags
+
+#[derive(Debug, Clone, Copy)]
+pub struct CpuidResult {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+pub fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    let (eax, ebx, ecx, edx): (u32, u32, u32, u32);
+
+    unsafe {
+        asm!("cpuid" :
+            "={eax}"(eax), "={ebx}"(ebx), "={ecx}"(ecx), "={edx}"(edx) :
+            "{eax}"(leaf), "{ecx}"(subleaf) ::
+            "volatile");
+    }
+
+    CpuidResult { eax: eax, ebx: ebx, ecx: ecx, edx: edx }
+}
+
+// Leaf 1, ECX
+pub fn has_x2apic() -> bool {
+    cpuid(1, 0).ecx & (1 << 21) != 0
+}
+
+// Leaf 1, EDX
+pub fn has_apic() -> bool {
+    cpuid(1, 0).edx & (1 << 9) != 0
+}
+
+// Leaf 1, EDX
+pub fn has_tsc() -> bool {
+    cpuid(1, 0).edx & (1 << 4) != 0
+}
+
+// Leaf 0x80000007, EDX: the TSC runs at a constant rate in every power state
+pub fn has_invariant_tsc() -> bool {
+    cpuid(0x8000_0000, 0).eax >= 0x8000_0007 && cpuid(0x8000_0007, 0).edx & (1 << 8) != 0
+}
@@ -6,8 +6,19 @@ use core::mem::size_of;
 use core::fmt::Write;
 use driver::vga;
 use driver::vga::Writer;
+use arch::x86_64::tss::TaskStateSegment;
 
-const GDT_LENGTH: usize = 3;
+//null, kernel code, kernel data, user data, user code, and a TSS descriptor
+//(which takes two entries). user data comes before user code, the order
+//SYSRET expects
+const GDT_LENGTH: usize = 7;
+
+//segment selectors, the user ones carry a requested privilege level of 3
+pub const KERNEL_CODE_SELECTOR: u16 = 1 << 3;
+pub const KERNEL_DATA_SELECTOR: u16 = 2 << 3;
+pub const USER_DATA_SELECTOR: u16 = 3 << 3 | 3;
+pub const USER_CODE_SELECTOR: u16 = 4 << 3 | 3;
+pub const TSS_SELECTOR: u16 = 5 << 3;
 
 //various binary flags that appear in the access field of a gdt entry
 //they determine the properties of the entry, and how data is manipulated/accessed
@@ -17,7 +28,9 @@ pub enum AccessFlags {
     ReadWrite = 0b00000010,
     Executable = 0b00001000, //indicate a code segment
     Present = 0b10000000, //indicate a valid sector
-    One = 0b00010000 //self-explanatory -- always set
+    One = 0b00010000, //self-explanatory -- always set (except for system segments)
+    Ring3 = 0b01100000, //descriptor privilege level 3, usable from user mode
+    TssAvailable = 0b00001001 //system segment type of an available 64-bit TSS
 }
 
 //various binary flags that appear in the granularity field of a gdt entry
@@ -26,18 +39,38 @@ pub enum GranularityFlags {
     LongMode64 = 0b0010
 }
 
-pub struct Gdt([GdtEntry; 3]);
+pub struct Gdt([GdtEntry; GDT_LENGTH]);
 
 impl Gdt {
     pub fn new() -> Gdt {
-        Gdt([GdtEntry::missing(); 3])
+        Gdt([GdtEntry::missing(); GDT_LENGTH])
     }
 
     pub fn set_entry(&mut self, vector: u8, entry: GdtEntry) {
        self.0[vector as usize] = entry;
     }
 
+    //a TSS descriptor is 16 bytes long, so it fills this entry and the next
+    //the TSS must live as long as the GDT
+    pub fn set_tss(&mut self, vector: u8, tss: *const TaskStateSegment) {
+        let base = tss as u64;
+        let limit = (size_of::<TaskStateSegment>() - 1) as u32;
+        let access = AccessFlags::TssAvailable as u8 | AccessFlags::Present as u8;
+
+        self.0[vector as usize] = GdtEntry::set_up(base as u32, limit, access, 0);
+        self.0[vector as usize + 1] = GdtEntry::upper_base(base);
+    }
+
     pub fn install(&'static self) {
+        self.load();
+
+        vga::okay();
+        write!(Writer::new(), "Success! Created 64-bit GDT at address 0x{:X}\n", self as *const _ as u64)
+            .expect("Unexpected failure in write!()");
+    }
+
+    //load the table and the kernel data segment, without reporting it
+    pub fn load(&'static self) {
         let mut ptr = GdtPointer::new();
         ptr.limit = (GDT_LENGTH as u16 * size_of::<GdtEntry>() as u16) - 1;
         ptr.base = self as *const _ as u64;
@@ -52,12 +85,6 @@ impl Gdt {
                 mov %ax, %ss" 
                 :: "r" (&ptr) : "memory");
         }
-
-        vga::okay();
-        unsafe {
-            write!(Writer::new(), "Success! Created 64-bit GDT at address 0x{:X}\n", ptr.base)
-                .expect("Unexpected failure in write!()");;
-        }
     }
 }
 
@@ -90,6 +117,18 @@ impl GdtEntry {
         }
     }
 
+    //second half of a 16-byte system descriptor, holding bits 32-63 of the base
+    pub fn upper_base(base_in: u64) -> GdtEntry {
+        GdtEntry {
+            limit_low: (base_in >> 32) as u16,
+            base_low: (base_in >> 48) as u16,
+            base_middle: 0,
+            access: 0,
+            granularity: 0,
+            base_high: 0
+        }
+    }
+
     pub fn set_up(base_in: u32, limit_in: u32, access_in: u8, gran_in: u8) -> GdtEntry {
         let temp_flags: u8 = ((limit_in >> 16) & 0x0F) as u8;
         let flags: u8 = temp_flags | ((gran_in << 4) &0xF0) as u8;
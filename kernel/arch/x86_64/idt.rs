@@ -5,11 +5,16 @@ use core::mem::size_of;
 use core::fmt::Write;
 use driver::vga;
 use driver::vga::Writer;
+use arch::x86_64::gdt;
 
 const IDT_LENGTH: usize = 256;
 
+//masks for the fields of the attribute byte
+const GATE_TYPE_MASK: u8 = 0b00001111;
+const RING_MASK: u8 = 0b01100000;
+
 //various binary flags that represent entry attributes
-enum EntryFlags {
+pub enum EntryFlags {
     Present = 0b10000000,
     //flags to determine gate type
     TaskGate = 0b0101,
@@ -23,6 +28,22 @@ enum EntryFlags {
     Ring3 = 0b1100000
 }
 
+//type of gate: interrupt gates clear IF on entry, trap gates leave it alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateType {
+    Interrupt,
+    Trap
+}
+
+//lowest privilege level allowed to raise a vector with `int`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivilegeLevel {
+    Ring0,
+    Ring1,
+    Ring2,
+    Ring3
+}
+
 pub struct Idt([IdtEntry; 256]);
 
 impl Idt {
@@ -30,11 +51,32 @@ impl Idt {
         Idt([IdtEntry::missing(); 256])
     }
 
-    pub fn set_handler(&mut self, vector: u8, func: u64) {
+    //install a ring 0 interrupt gate, returning its options so they can be changed
+    pub fn set_handler(&mut self, vector: u8, func: u64) -> &mut EntryOptions {
         self.0[vector as usize] = IdtEntry::new(func);
+        &mut self.0[vector as usize].options
+    }
+
+    //options of an existing entry
+    pub fn options(&mut self, vector: u8) -> &mut EntryOptions {
+        &mut self.0[vector as usize].options
+    }
+
+    //remove the handler for a vector entirely, raising it will then cause a GPF
+    pub fn clear_handler(&mut self, vector: u8) {
+        self.0[vector as usize] = IdtEntry::missing();
     }
 
     pub fn install(&'static self) {
+        self.load();
+
+        vga::okay();
+        write!(Writer::new(), "Success! Created 64-bit IDT at address 0x{:X}\n", self as *const _ as u64)
+            .expect("Unexpected failure in write!()");
+    }
+
+    //load the table, without reporting it
+    pub fn load(&'static self) {
         let mut ptr = IdtPointer::new();
         ptr.limit = (IDT_LENGTH as u16 * size_of::<IdtEntry>() as u16) - 1;
         ptr.base = self as *const _ as u64;
@@ -42,12 +84,6 @@ impl Idt {
         unsafe {
             asm!("lidt ($0)" :: "r" (&ptr) : "memory");
         }
-
-        vga::okay();
-        unsafe {
-            write!(Writer::new(), "Success! Created 64-bit IDT at address 0x{:X}\n", ptr.base)
-                .expect("Unexpected failure in write!()");;
-        }
     }
 }
 
@@ -73,11 +109,8 @@ impl IdtPointer {
 pub struct IdtEntry {
     //base: offset in memory of entry
     base_low: u16,
-    //selector: pointer to interrupt handler function
-    selector: u16,
-    zero1: u8,
-    //entry attributes
-    flags: u8,
+    //selector, stack index and attributes
+    options: EntryOptions,
     base_middle: u16,
     base_high: u32,
     zero2: u32
@@ -87,9 +120,7 @@ impl IdtEntry {
     pub const fn missing() -> IdtEntry {
         IdtEntry {
             base_low: 0,
-            selector: 0,
-            zero1: 0,
-            flags: 0,
+            options: EntryOptions::minimal(),
             base_middle: 0,
             base_high: 0,
             zero2: 0
@@ -102,12 +133,91 @@ impl IdtEntry {
             base_middle: (pointer >> 16) as u16,
             base_high: (pointer >> 32) as u32,
 
-            selector: 8,
+            options: EntryOptions::new(),
 
-            zero1: 0,
-            zero2: 0,
+            zero2: 0
+        }
+    }
+}
 
-            flags: EntryFlags::InterruptGate as u8 | EntryFlags::Present as u8
+//the part of an idt entry between the two halves of the handler address
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct EntryOptions {
+    //selector: code segment the handler runs in
+    selector: u16,
+    //interrupt stack table index (plus one), zero to stay on the current stack
+    ist: u8,
+    //entry attributes
+    flags: u8
+}
+
+impl EntryOptions {
+    //not present, no handler
+    const fn minimal() -> EntryOptions {
+        EntryOptions {
+            selector: 0,
+            ist: 0,
+            flags: EntryFlags::InterruptGate as u8
+        }
+    }
+
+    //present ring 0 interrupt gate in the kernel code segment
+    fn new() -> EntryOptions {
+        EntryOptions {
+            selector: gdt::KERNEL_CODE_SELECTOR,
+            ist: 0,
+            flags: EntryFlags::InterruptGate as u8 | EntryFlags::Present as u8 | EntryFlags::Ring0 as u8
+        }
+    }
+
+    //a vector that is not present raises a GPF instead of calling its handler
+    pub fn set_present(&mut self, present: bool) -> &mut EntryOptions {
+        if present {
+            self.flags |= EntryFlags::Present as u8;
+        } else {
+            self.flags &= !(EntryFlags::Present as u8);
         }
+        self
+    }
+
+    pub fn set_gate_type(&mut self, gate: GateType) -> &mut EntryOptions {
+        let bits = match gate {
+            GateType::Interrupt => EntryFlags::InterruptGate as u8,
+            GateType::Trap => EntryFlags::TrapGate as u8
+        };
+
+        self.flags = (self.flags & !GATE_TYPE_MASK) | bits;
+        self
+    }
+
+    pub fn set_privilege_level(&mut self, ring: PrivilegeLevel) -> &mut EntryOptions {
+        let bits = match ring {
+            PrivilegeLevel::Ring0 => EntryFlags::Ring0 as u8,
+            PrivilegeLevel::Ring1 => EntryFlags::Ring1 as u8,
+            PrivilegeLevel::Ring2 => EntryFlags::Ring2 as u8,
+            PrivilegeLevel::Ring3 => EntryFlags::Ring3 as u8
+        };
+
+        self.flags = (self.flags & !RING_MASK) | bits;
+        self
+    }
+
+    //switch to a known-good stack from the TSS interrupt stack table on entry
+    pub fn set_stack_index(&mut self, index: u8) -> &mut EntryOptions {
+        assert!(index < 7, "IST index out of range");
+        self.ist = index + 1;
+        self
+    }
+
+    //stay on the current stack on entry
+    pub fn clear_stack_index(&mut self) -> &mut EntryOptions {
+        self.ist = 0;
+        self
+    }
+
+    pub fn set_code_selector(&mut self, selector: u16) -> &mut EntryOptions {
+        self.selector = selector;
+        self
     }
 }
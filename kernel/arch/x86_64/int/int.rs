@@ -16,3 +16,31 @@ pub fn disable() {
         asm!("cli");
     }
 }
+
+//check the interrupt flag in RFLAGS
+pub fn are_enabled() -> bool {
+    let rflags: u64;
+
+    unsafe {
+        asm!("pushfq; popq $0" : "=r"(rflags) :: "memory" : "volatile");
+    }
+
+    rflags & (1 << 9) != 0
+}
+
+//run a closure with interrupts disabled, restoring the previous state after
+pub fn without_interrupts<F, R>(f: F) -> R where F: FnOnce() -> R {
+    let enabled = are_enabled();
+
+    if enabled {
+        disable();
+    }
+
+    let result = f();
+
+    if enabled {
+        enable();
+    }
+
+    result
+}
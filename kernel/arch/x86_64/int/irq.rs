@@ -0,0 +1,147 @@
+//irq.rs
+//dispatches hardware interrupts to handlers registered at runtime
+
+//***************************************//
+// Vectors from 32 upwards are routed    //
+// here by isr_dispatch, apart from the  //
+// system call vector                    //
+//***************************************//
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::RwLock;
+use arch::dev::interrupt_controller::{InterruptController, LEGACY_PIC};
+use arch::x86_64::int::int;
+use arch::x86_64::int::isr::ExceptionContext;
+use arch::x86_64::int::syscall::SYSCALL_VECTOR;
+use driver::vga;
+use task::scheduler;
+use driver::vga::Writer;
+
+//first vector used for hardware interrupts
+pub const IRQ_BASE: u8 = 32;
+//number of vectors with an irq stub
+pub const IRQ_COUNT: usize = 256 - IRQ_BASE as usize;
+
+pub type IrqHandler = fn(irq: u8);
+
+static HANDLERS: RwLock<[Option<IrqHandler>; IRQ_COUNT]> = RwLock::new([None; IRQ_COUNT]);
+
+//controller that masks, routes and acknowledges irq lines, the 8259s until
+//the APICs are brought up
+static CONTROLLER: RwLock<&'static dyn InterruptController> = RwLock::new(&LEGACY_PIC);
+
+//interrupts that arrived with no handler registered
+static UNHANDLED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+pub enum IrqError {
+    InvalidIrq,
+    AlreadyRegistered,
+    //the irq's vector is taken by something other than hardware
+    Reserved
+}
+
+//install a handler for an irq line, and unmask the line
+pub fn register_irq(irq: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    if irq as usize >= IRQ_COUNT {
+        return Err(IrqError::InvalidIrq);
+    }
+    if irq == SYSCALL_VECTOR - IRQ_BASE {
+        return Err(IrqError::Reserved);
+    }
+
+    //interrupts must be off while the table is locked for writing, or a
+    //dispatch on this CPU would spin forever on the read lock
+    int::without_interrupts(|| {
+        let mut handlers = HANDLERS.write();
+
+        if handlers[irq as usize].is_some() {
+            return Err(IrqError::AlreadyRegistered);
+        }
+        handlers[irq as usize] = Some(handler);
+        Ok(())
+    })?;
+
+    controller().unmask(irq);
+    Ok(())
+}
+
+//mask an irq line, and remove its handler
+pub fn unregister_irq(irq: u8) -> Result<(), IrqError> {
+    if irq as usize >= IRQ_COUNT {
+        return Err(IrqError::InvalidIrq);
+    }
+
+    controller().mask(irq);
+
+    int::without_interrupts(|| {
+        HANDLERS.write()[irq as usize] = None;
+    });
+
+    Ok(())
+}
+
+pub fn controller() -> &'static dyn InterruptController {
+    *CONTROLLER.read()
+}
+
+//hand irq delivery over to another controller, lines with a registered
+//handler are unmasked on the new controller
+pub fn set_controller(new: &'static dyn InterruptController) {
+    int::without_interrupts(|| {
+        let handlers = HANDLERS.read();
+        let mut current = CONTROLLER.write();
+
+        current.disable();
+        for (irq, handler) in handlers.iter().enumerate() {
+            if handler.is_some() {
+                new.unmask(irq as u8);
+            }
+        }
+        *current = new;
+    });
+
+    vga::okay();
+    write!(Writer::new(), "Interrupts now delivered by the {}\n", new.name())
+        .expect("Unexpected failure in write!()");
+}
+
+pub fn unhandled_count() -> usize {
+    UNHANDLED.load(Ordering::Relaxed)
+}
+
+pub fn dispatch(ctx: &mut ExceptionContext) {
+    let vector = ctx.vector;
+    let irq = (vector as u8).wrapping_sub(IRQ_BASE);
+
+    let controller = controller();
+
+    //spurious interrupts must not be acknowledged
+    if controller.is_spurious(irq) {
+        return;
+    }
+
+    per_cpu!(irq_depth += 1);
+
+    //copy the handler out, so the lock is not held while it runs
+    let handler = HANDLERS.read()[irq as usize];
+
+    match handler {
+        Some(handler) => handler(irq),
+        None => {
+            if UNHANDLED.fetch_add(1, Ordering::Relaxed) == 0 {
+                vga::error();
+                write!(Writer::new(), "Unhandled IRQ {} (vector {})\n", irq, vector)
+                    .expect("Unexpected failure in write!()");
+            }
+        }
+    }
+
+    //end of interrupt is always sent here, handlers must not do it themselves
+    controller.eoi(irq);
+    per_cpu!(irq_depth -= 1);
+
+    //switching threads is only safe once the controller has been acknowledged
+    scheduler::preempt();
+}
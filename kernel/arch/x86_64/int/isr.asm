@@ -1,31 +1,11 @@
 ;isr.asm
 
-;defines functions and macros for use handling interrupts
-;makes sure all correct information is preserved and restored, and the
-;interrupt is passed to the right handler
+;defines the entry stubs for every interrupt vector
+;each stub pushes an error code (if the CPU did not) and its vector number, then
+;joins a common path that saves all registers and passes them to isr_dispatch
 
-;default handlers
-global isr_default
-global isr_default_err
-
-;exceptions
-global divide_by_zero_wrapper
-global debug_wrapper
-global breakpoint_wrapper
-global overflow_wrapper
-global bounds_wrapper
-global opcode_wrapper
-global device_na_wrapper
-global double_fault_wrapper
-global gpf_wrapper
-global x87_float_wrapper
-global page_fault_wrapper
-
-;interrupts
-global pit_wrapper
-global keyboard_wrapper
-global com1_wrapper
-global isr_spurious
+;addresses of the 256 stubs, indexed by vector
+global isr_stub_table
 
 section .text
 bits 64
@@ -67,213 +47,55 @@ bits 64
     pop rax
   %endmacro
 
-  align 4
-  isr_default:
-    mov rdi, rsp
-    PUSH_ALL
-    sub rsp, 8
-
-    extern isr_default_handler
-    call isr_default_handler
-
-    add rsp, 8
-    POP_ALL
-    iretq
-
-  align 4
-  isr_default_err:
-    pop rsi
-    mov rdi, rsp
-    PUSH_ALL
-    sub rsp, 8
-
-    extern isr_default_err_handler
-    call isr_default_err_handler
-
-    add rsp, 8
-    POP_ALL
-    iretq
-
-  align 4
-  isr_spurious:
-    PUSH_ALL
-
-    POP_ALL
-    iretq
-
-  align 4
-  divide_by_zero_wrapper:
-    mov rdi, rsp
-    sub rsp, 8
-    PUSH_ALL
-
-    extern divide_by_zero_handler
-    call divide_by_zero_handler
-
-    add rsp, 8
-    POP_ALL
-    iretq
-
-  align 4
-  breakpoint_wrapper:
-    mov rdi, rsp
-    sub rsp, 8
-    PUSH_ALL
-
-    extern breakpoint_handler
-    call breakpoint_handler
-
-    add rsp, 8
-    POP_ALL
-    iretq
-  
-  align 4
-  debug_wrapper:
-    mov rdi, rsp
-    sub rsp, 8
-    PUSH_ALL
-
-    extern debug_handler
-    call debug_handler
-
-    add rsp, 8
-    POP_ALL
-    iretq
-
-  align 4
-  overflow_wrapper:
-    mov rdi, rsp
-    sub rsp, 8
-    PUSH_ALL
-
-    extern overflow_handler
-    call overflow_handler
-
-    add rsp, 8
-    POP_ALL
-    iretq
-
-  align 4
-  bounds_wrapper:
-    mov rdi, rsp
-    sub rsp, 8
-    PUSH_ALL
-
-    extern bounds_handler
-    call bounds_handler
-
-    add rsp, 8
-    POP_ALL
-    iretq
-
-  align 4
-  opcode_wrapper:
-    mov rdi, rsp
-    sub rsp, 8
-    PUSH_ALL
-
-    extern opcode_handler
-    call opcode_handler
-
-    add rsp, 8
-    POP_ALL
-    iretq
-
-  align 4
-  device_na_wrapper:
-    mov rsi, rsp
-    sub rsp, 8
-    PUSH_ALL
-    
-    extern device_na_handler
-    call device_na_handler
-
-    add rsp, 8
-    POP_ALL
-    iretq
-
-  align 4
-  double_fault_wrapper:
-    pop rsi
-    mov rdi, rsp
-    sub rsp, 8
-    PUSH_ALL
-
-    extern double_fault_handler
-    call double_fault_handler
-
-    add rsp, 8
-    POP_ALL
-    iretq
-
-  align 4
-  gpf_wrapper:
-    pop rsi
-    mov rdi, rsp
-    sub rsp, 8
-    PUSH_ALL
-
-    extern gpf_handler
-    call gpf_handler
-
-    add rsp, 8
-    POP_ALL
-    iretq
-
-  align 4
-  x87_float_wrapper:
-    mov rdi, rsp
-    sub rsp, 8
-    PUSH_ALL
-
-    extern x87_float_handler
-    call x87_float_handler
-
-    add rsp, 8
-    POP_ALL
-    iretq
-
-  align 4
-  page_fault_wrapper:
-    pop rsi
-    mov rdi, rsp
-    sub rsp, 8
-    PUSH_ALL
-
-    extern page_fault_handler
-    call page_fault_handler
-
-    add rsp, 8
-    POP_ALL
-    iretq
-
-  align 4
-  pit_wrapper:
-    PUSH_ALL
-
-    extern pit_handler
-    call pit_handler
-
-    POP_ALL
-    iretq
-
-  align 4
-  keyboard_wrapper:
-    PUSH_ALL
-
-    extern keyboard_handler
-    call keyboard_handler
-
-    POP_ALL
-    iretq
-
-  align 4
-  com1_wrapper:
-    PUSH_ALL
-
-    extern com1_handler
-    call com1_handler
-
-    POP_ALL
-    iretq
-
+  ;common path for every vector
+  ;the stack now matches ExceptionContext in isr.rs: saved registers, vector,
+  ;error code and the hardware frame. handlers may modify it before we return
+  ;coming from ring 3, swap in the kernel's gs base (see percpu.rs)
+  ;the saved cs is past the vector and error code
+  align 16
+  isr_common:
+    test qword [rsp + 24], 3
+    jz .from_kernel
+    swapgs
+  .from_kernel:
+    PUSH_ALL
+    cld
+
+    mov rdi, rsp        ;pointer to the ExceptionContext
+
+    extern isr_dispatch
+    call isr_dispatch
+
+    POP_ALL
+    add rsp, 16         ;pop vector and error code
+
+    ;returning to ring 3, give the user gs base back
+    test qword [rsp + 8], 3
+    jz .to_kernel
+    swapgs
+  .to_kernel:
+    iretq
+
+  ;one stub per vector
+  ;the CPU pushes an error code for vectors 8, 10-14, 17, 21, 29 and 30 only
+  %assign vector 0
+  %rep 256
+    align 16
+    isr_stub_%[vector]:
+    %if vector == 8 || (vector >= 10 && vector <= 14) || vector == 17 || vector == 21 || vector == 29 || vector == 30
+    %else
+      push 0
+    %endif
+      push vector
+      jmp isr_common
+  %assign vector vector + 1
+  %endrep
+
+section .rodata
+  align 8
+  isr_stub_table:
+  %assign vector 0
+  %rep 256
+    dq isr_stub_%[vector]
+  %assign vector vector + 1
+  %endrep
@@ -1,229 +1,254 @@
 //isr.rs
 //defines methods for interrupts
+//every vector enters through a stub in isr.asm, which calls isr_dispatch
 
 //***************************************//
 // Exception list at:                    //
 // https://wiki.osdev.org/Exceptions     //
 //***************************************//
 
-use arch::dev::pic;
-use arch::dev::pit;
 use driver::vga::Writer;
-use driver::vga::print_char;
-use driver::kbd::get_char;
-use driver::com;
+use arch::x86_64::control;
+use arch::x86_64::mem::stack;
+use arch::x86_64::int::irq;
+use arch::x86_64::int::page_fault;
+use arch::x86_64::int::page_fault::PageFault;
+use arch::x86_64::int::syscall;
+use arch::x86_64::mem;
+use task::process;
+use core::fmt;
 use core::fmt::Write;
 
-const PIT_OFFSET: u8 = 1;
-const KBD_OFFSET: u8 = 2;
-const COM1_OFFSET: u8 = 4;
-
-#[derive(Debug)]
+//pushed by the CPU on every interrupt
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct InterruptFrame {
     pub instruction_pointer: u64,
-    code_segment: u64,
-    cpu_flags: u64,
-    stack_pointer: u64,
-    stack_segment: u64
+    pub code_segment: u64,
+    pub cpu_flags: u64,
+    pub stack_pointer: u64,
+    pub stack_segment: u64
+}
+
+//everything saved on entry to an interrupt, laid out as the stack is built by
+//isr_common. changes made by a handler are restored when it returns
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ExceptionContext {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    //zero for vectors without an error code
+    pub error_code: u64,
+    pub frame: InterruptFrame
+}
+
+impl ExceptionContext {
+    //whether the interrupted code was running in ring 3
+    pub fn from_user(&self) -> bool {
+        self.frame.code_segment & 3 == 3
+    }
 }
 
-// TODO: Bitflags?
-#[repr(u64)]
-enum PageFaultError {
-    ProtectionViolation = 0b00000001,
-    AttemptToWrite = 0b00000010,
-    UserMode = 0b00000100,
-    TableError = 0b00001000,
-    InstructionFetch = 0b00010000,
+//register dump for crash reports
+impl fmt::Display for ExceptionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RAX={:016X} RBX={:016X} RCX={:016X}\n", self.rax, self.rbx, self.rcx)?;
+        write!(f, "RDX={:016X} RSI={:016X} RDI={:016X}\n", self.rdx, self.rsi, self.rdi)?;
+        write!(f, "RBP={:016X} RSP={:016X} R8 ={:016X}\n", self.rbp, self.frame.stack_pointer, self.r8)?;
+        write!(f, "R9 ={:016X} R10={:016X} R11={:016X}\n", self.r9, self.r10, self.r11)?;
+        write!(f, "R12={:016X} R13={:016X} R14={:016X}\n", self.r12, self.r13, self.r14)?;
+        write!(f, "R15={:016X} RIP={:016X} RFL={:016X}\n", self.r15, self.frame.instruction_pointer, self.frame.cpu_flags)?;
+        write!(f, "CS={:04X} SS={:04X} VECTOR={} ERROR={:#X}\n", self.frame.code_segment,
+            self.frame.stack_segment, self.vector, self.error_code)
+    }
 }
 
+const EXCEPTION_NAMES: [&str; 32] = [
+    "DIVIDE BY ZERO", "DEBUG", "NON-MASKABLE INTERRUPT", "BREAK POINT",
+    "OVERFLOW", "OUT-OF-BOUNDS", "INVALID OPCODE", "DEVICE NOT FOUND",
+    "DOUBLE FAULT", "COPROCESSOR SEGMENT OVERRUN", "INVALID TSS", "SEGMENT NOT PRESENT",
+    "STACK SEGMENT FAULT", "GPF", "PAGE FAULT", "RESERVED",
+    "x87 FLOATING POINT", "ALIGNMENT CHECK", "MACHINE CHECK", "SIMD FLOATING POINT",
+    "VIRTUALISATION FAULT", "CONTROL PROTECTION", "RESERVED", "RESERVED",
+    "RESERVED", "RESERVED", "RESERVED", "RESERVED",
+    "HYPERVISOR INJECTION", "VMM COMMUNICATION", "SECURITY EXCEPTION", "RESERVED"
+];
+
 #[no_mangle]
 #[linkage = "external"]
-pub extern fn isr_default_handler(frame: &InterruptFrame) -> ! {
-    let frame = &*frame;
-    write!(Writer::new(), "EXCEPTION: UNHANDLED EXCEPTION at instruction {:#X}\n{:#?}\n\n",
-        frame.instruction_pointer, frame).expect("Unexpected failure in write!()");
+pub extern fn isr_dispatch(ctx: &mut ExceptionContext) {
+    // An exception raised by user code ends that process, not the kernel
+    if ctx.vector < 32 && ctx.from_user() && kills_process(ctx.vector) {
+        user_fault(EXCEPTION_NAMES[ctx.vector as usize], ctx);
+    }
 
-    loop {}
+    match ctx.vector {
+        0 => divide_by_zero_handler(ctx),
+        1 => debug_handler(ctx),
+        2 => nmi_handler(ctx),
+        3 => breakpoint_handler(ctx),
+        4 => overflow_handler(ctx),
+        5 => bounds_handler(ctx),
+        6 => opcode_handler(ctx),
+        7 => device_na_handler(ctx),
+        8 => double_fault_handler(ctx),
+        13 => gpf_handler(ctx),
+        14 => page_fault_handler(ctx),
+        16 => x87_float_handler(ctx),
+        18 => machine_check_handler(ctx),
+        0..=31 => isr_default_handler(ctx),
+        0x80 => syscall::int80_handler(ctx),
+        _ => irq::dispatch(ctx),
+    }
 }
 
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn isr_default_err_handler(frame: &InterruptFrame) -> ! {
-	let frame = &*frame;
-	write!(Writer::new(), "EXCEPTION: UNHANDLED EXCEPTION at instruction {:X}\n{:#?}\n\n",
-		frame.instruction_pointer, frame).expect("Unexpected failure in write!()");
+// Report an exception with the full register state, then halt
+fn fatal(name: &str, ctx: &ExceptionContext) -> ! {
+    write!(Writer::new(), "EXCEPTION: {} at instruction {:#X}\n{}\n",
+        name, ctx.frame.instruction_pointer, ctx).expect("Unexpected failure in write!()");
 
     loop {}
 }
 
-// Vector 0
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn divide_by_zero_handler(frame: &InterruptFrame) -> ! {
-    let frame = &*frame ;
-    write!(Writer::new(), "EXCEPTION: DIVIDE BY ZERO at instruction {:#X}\n{:#?}\n\n",
-        frame.instruction_pointer, frame).expect("Unexpected failure in write!()");
+// Halt with a stack overflow report if the faulting address is on the guard
+// page of a stack
+fn check_stack_overflow(address: usize, ctx: &ExceptionContext) {
+    if let Some(overflowed) = stack::find_overflowed(address) {
+        write!(Writer::new(), "EXCEPTION: STACK OVERFLOW in stack \"{}\" at instruction {:#X}, guard page {:#X}\n{}\n",
+            overflowed.name(), ctx.frame.instruction_pointer, overflowed.guard_page(), ctx)
+            .expect("Unexpected failure in write!()");
 
-    loop {}
+        loop {}
+    }
+}
+
+// NMIs and machine checks are not caused by the code they interrupt,
+// breakpoints resume, and page faults may still be resolved
+fn kills_process(vector: u64) -> bool {
+    match vector {
+        2 | 3 | 14 | 18 => false,
+        _ => true,
+    }
+}
+
+// Report an exception raised in ring 3, then kill the process that caused it
+fn user_fault(name: &str, ctx: &ExceptionContext) -> ! {
+    write!(Writer::new(), "EXCEPTION: {} in user mode at instruction {:#X}\n{}\n",
+        name, ctx.frame.instruction_pointer, ctx).expect("Unexpected failure in write!()");
+
+    process::kill_current(name)
+}
+
+pub fn isr_default_handler(ctx: &mut ExceptionContext) -> ! {
+    fatal(EXCEPTION_NAMES[ctx.vector as usize], ctx)
+}
+
+// Vector 0
+pub fn divide_by_zero_handler(ctx: &mut ExceptionContext) -> ! {
+    fatal("DIVIDE BY ZERO", ctx)
 }
 
 // Vector 1
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn debug_handler(frame: &InterruptFrame) -> ! {
-    let frame = &*frame;
-    write!(Writer::new(), "EXCEPTION: DEBUG at instruction {:#X}\n{:#?}\n\n",
-        frame.instruction_pointer, frame).expect("Unexpected failure in write!()");
+pub fn debug_handler(ctx: &mut ExceptionContext) -> ! {
+    fatal("DEBUG", ctx)
+}
 
-    loop {}
+// Vector 2
+// Runs on its own IST stack
+pub fn nmi_handler(ctx: &mut ExceptionContext) -> ! {
+    fatal("NON-MASKABLE INTERRUPT", ctx)
 }
 
 // Vector 3
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn breakpoint_handler(frame: &InterruptFrame) {
-    let frame = &*frame;
-    write!(Writer::new(), "EXCEPTION: BREAK POINT at instruction {:#X}\n{:#?}\n\n",
-        frame.instruction_pointer, frame).expect("Unexpected failure in write!()");
+// A trap, so the saved instruction pointer is already past the int3 and
+// returning resumes execution
+pub fn breakpoint_handler(ctx: &mut ExceptionContext) {
+    write!(Writer::new(), "EXCEPTION: BREAK POINT at instruction {:#X}\n{}\n",
+        ctx.frame.instruction_pointer, ctx).expect("Unexpected failure in write!()");
 }
 
 // Vector 4
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn overflow_handler(frame: &InterruptFrame) -> ! {
-    let frame = &*frame;
-    write!(Writer::new(), "EXCEPTION: OVERFLOW at instruction {:#X}\n{:#?}\n\n",
-        frame.instruction_pointer, frame).expect("Unexpected failure in write!()");
-
-    loop {}
+pub fn overflow_handler(ctx: &mut ExceptionContext) -> ! {
+    fatal("OVERFLOW", ctx)
 }
 
 // Vector 5
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn bounds_handler(frame: &InterruptFrame) -> ! {
-    let frame = &*frame;
-    write!(Writer::new(), "EXCEPTION: OUT-OF-BOUNDS at instruction {:#X}\n{:#?}\n\n",
-        frame.instruction_pointer, frame).expect("Unexpected failure in write!()");
-
-    loop {}
+pub fn bounds_handler(ctx: &mut ExceptionContext) -> ! {
+    fatal("OUT-OF-BOUNDS", ctx)
 }
 
 // Vector 6
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn opcode_handler(frame: &InterruptFrame) -> ! {
-    let frame = &*frame;
-    write!(Writer::new(), "EXCEPTION: INVALID OPCODE at instruction {:#X}\n{:#?}\n\n",
-        frame.instruction_pointer, frame).expect("Unexpected failure in write!()");
-
-    loop {}
+pub fn opcode_handler(ctx: &mut ExceptionContext) -> ! {
+    fatal("INVALID OPCODE", ctx)
 }
 
 // Vector 7
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn device_na_handler(frame: &InterruptFrame) -> ! {
-    let frame = &*frame;
-    write!(Writer::new(), "EXCEPTION: DEVICE NOT FOUND at instruction {:#X}\n{:#?}\n\n",
-        frame.instruction_pointer, frame).expect("Unexpected failure in write!()");
-
-    loop {}
+pub fn device_na_handler(ctx: &mut ExceptionContext) -> ! {
+    fatal("DEVICE NOT FOUND", ctx)
 }
 
 // Vector 8
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn double_fault_handler(frame: &InterruptFrame) -> ! {
-    let frame = &*frame;
-    write!(Writer::new(), "EXCEPTION: DOUBLE FAULT at instruction {:#X}\n{:#?}\n\n",
-        frame.instruction_pointer, frame).expect("Unexpected failure in write!()");
+// Runs on its own IST stack, so a kernel stack overflow ends up here rather
+// than triple faulting
+pub fn double_fault_handler(ctx: &mut ExceptionContext) -> ! {
+    // A page fault on a guard page cannot push its frame onto the overflowed
+    // stack, so it escalates to a double fault
+    check_stack_overflow(control::read_cr2() as usize, ctx);
 
-    loop {}
+    fatal("DOUBLE FAULT", ctx)
 }
 
 // Vector 13
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn gpf_handler(frame: &InterruptFrame, code: u64) -> ! {
-    let frame = &*frame;
-    write!(Writer::new(), "EXCEPTION: GPF at instruction {:#X}, error {:#X}\n{:#?}\n\n",
-        frame.instruction_pointer, code, frame).expect("Unexpected failure in write!()");
-
-    loop {}
+pub fn gpf_handler(ctx: &mut ExceptionContext) -> ! {
+    fatal("GPF", ctx)
 }
 
 // Vector 14
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn page_fault_handler(frame: &InterruptFrame, code: u64) -> ! {
-    let frame = &*frame;
-    let code_str;
-
-    // TODO: TIDY UP
-    match code {
-        code if code == PageFaultError::ProtectionViolation as u64 => code_str = "PROTECTION VIOLATION",
-        code if code == PageFaultError::AttemptToWrite as u64 => code_str = "ATTEMPTED TO WRITE",
-        code if code == PageFaultError::UserMode as u64 => code_str = "USER MODE",
-        code if code == PageFaultError::TableError as u64 => code_str = "ERROR IN TABLE",
-        code if code == PageFaultError::InstructionFetch as u64 => code_str = "INSTRUCTION FETCH",
-        _ => code_str = "UNKNOWN ERROR",
-    }
+// Returns only if the page fault hook resolved the fault
+pub fn page_fault_handler(ctx: &mut ExceptionContext) {
+    let fault = PageFault::from_context(ctx);
 
-    write!(Writer::new(), "EXCEPTION: PAGE FAULT at instruction {:#X}, error: {:#}\n{:#?}\n\n",
-        frame.instruction_pointer, code_str, frame).expect("Unexpected failure in write!()");
+    // Faults on a guard page mean the stack above it has overflowed
+    check_stack_overflow(fault.address, ctx);
 
-    loop {}
-}
-
-// Vector 16
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn x87_float_handler(frame: &InterruptFrame) -> ! {
-    let frame = &*frame;
-    write!(Writer::new(), "EXCEPTION: x87 FLOATING POINT at instruction {:#X}\n{:#?}\n\n",
-        frame.instruction_pointer, frame).expect("Unexpected failure in write!()");
-
-    loop {}
-}
-
-// Vector 32
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn pit_handler() {
-    unsafe {
-        pit::TICKS += 1;
+    if page_fault::try_resolve(&fault, ctx) {
+        return;
+    }
 
-        if pit::TICKS >= 0xFFFFFFFF - 1 {
-            pit::TICKS = 0;
-        }
+    if ctx.from_user() {
+        write!(Writer::new(), "EXCEPTION: PAGE FAULT in user mode at instruction {:#X}: {}\n",
+            ctx.frame.instruction_pointer, fault).expect("Unexpected failure in write!()");
+        process::kill_current("PAGE FAULT");
     }
-    
-    pic::ack(PIT_OFFSET);
-}
 
-// Vector 33
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn keyboard_handler() {
-    let c = get_char();
+    write!(Writer::new(), "EXCEPTION: PAGE FAULT at instruction {:#X}: {}\nerror: {:?}\n{}{}\n",
+        ctx.frame.instruction_pointer, fault, fault.error, mem::walk(fault.address), ctx)
+        .expect("Unexpected failure in write!()");
 
-    match c {
-        Some(res) => print_char(res, 0x07),
-        // Do nothing if NONE returned
-        None => {}
-    }
-    
-    pic::ack(KBD_OFFSET);
+    loop {}
 }
 
-// Vector 36
-#[no_mangle]
-#[linkage = "external"]
-pub extern fn com1_handler() {
-    write!(Writer::new(), "COM1 INPUT RECEIVED: {}", com::read() as char);
-    pic::ack(COM1_OFFSET);
+// Vector 16
+pub fn x87_float_handler(ctx: &mut ExceptionContext) -> ! {
+    fatal("x87 FLOATING POINT", ctx)
 }
 
+// Vector 18
+// Runs on its own IST stack
+pub fn machine_check_handler(ctx: &mut ExceptionContext) -> ! {
+    fatal("MACHINE CHECK", ctx)
+}
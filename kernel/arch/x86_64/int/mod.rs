@@ -1,2 +1,5 @@
 pub mod isr;
 pub mod int;
+pub mod irq;
+pub mod page_fault;
+pub mod syscall;
@@ -0,0 +1,103 @@
+//page_fault.rs
+//decodes page faults, and lets the memory manager resolve them
+
+use core::fmt;
+use spin::RwLock;
+use arch::x86_64::control;
+use arch::x86_64::int::isr::ExceptionContext;
+use arch::x86_64::mem::VirtualAddress;
+
+bitflags! {
+    //error code pushed by the CPU for a page fault
+    pub struct PageFaultErrorCode: u64 {
+        //set: protection violation, clear: page not present
+        const PRESENT = 1 << 0;
+        //set: the access was a write, clear: a read
+        const WRITE = 1 << 1;
+        //set: the access came from ring 3
+        const USER = 1 << 2;
+        //a reserved bit was set in a paging-structure entry
+        const RESERVED_BIT = 1 << 3;
+        //the access was an instruction fetch
+        const INSTRUCTION_FETCH = 1 << 4;
+        //protection key violation
+        const PROTECTION_KEY = 1 << 5;
+        //shadow stack access
+        const SHADOW_STACK = 1 << 6;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PageFault {
+    //faulting linear address, from CR2
+    pub address: VirtualAddress,
+    pub error: PageFaultErrorCode,
+    pub instruction_pointer: u64,
+}
+
+impl PageFault {
+    pub fn from_context(ctx: &ExceptionContext) -> PageFault {
+        PageFault {
+            address: control::read_cr2() as VirtualAddress,
+            error: PageFaultErrorCode::from_bits_truncate(ctx.error_code),
+            instruction_pointer: ctx.frame.instruction_pointer,
+        }
+    }
+}
+
+//one line description, such as "kernel write to a not-present page"
+impl fmt::Display for PageFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mode = if self.error.contains(PageFaultErrorCode::USER) { "user" } else { "kernel" };
+
+        let access = if self.error.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            "instruction fetch"
+        } else if self.error.contains(PageFaultErrorCode::WRITE) {
+            "write"
+        } else {
+            "read"
+        };
+
+        let cause = if self.error.contains(PageFaultErrorCode::RESERVED_BIT) {
+            "a paging-structure entry with a reserved bit set"
+        } else if self.error.contains(PageFaultErrorCode::PROTECTION_KEY) {
+            "a page denied by its protection key"
+        } else if self.error.contains(PageFaultErrorCode::SHADOW_STACK) {
+            "a shadow stack page"
+        } else if self.error.contains(PageFaultErrorCode::PRESENT) {
+            "a page without the required permissions"
+        } else {
+            "a not-present page"
+        };
+
+        write!(f, "{} {} of {:#X} hit {}", mode, access, self.address, cause)
+    }
+}
+
+//called before a page fault is reported. returning true means the fault has
+//been resolved, and the faulting instruction is retried
+pub type PageFaultHook = fn(fault: &PageFault, ctx: &mut ExceptionContext) -> bool;
+
+static HOOK: RwLock<Option<PageFaultHook>> = RwLock::new(None);
+
+pub fn set_hook(hook: PageFaultHook) {
+    *HOOK.write() = Some(hook);
+}
+
+pub fn clear_hook() {
+    *HOOK.write() = None;
+}
+
+//give the registered hook a chance to resolve the fault
+pub fn try_resolve(fault: &PageFault, ctx: &mut ExceptionContext) -> bool {
+    //never spin in a fault handler, the lock may be held by the faulting code
+    let hook = match HOOK.try_read() {
+        Some(hook) => *hook,
+        None => return false,
+    };
+
+    match hook {
+        Some(hook) => hook(fault, ctx),
+        None => false,
+    }
+}
@@ -0,0 +1,60 @@
+;syscall.asm
+
+;entry point of the SYSCALL instruction, loaded into IA32_LSTAR by syscall.rs
+;the CPU arrives here in ring 0 with the user rip in rcx, the user rflags in r11,
+;interrupts masked by IA32_FMASK, and still on the user stack
+
+global syscall_entry
+
+;offsets into PerCpu, must match percpu::offset, which is checked
+;against the struct when each CPU's PerCpu is set up
+PERCPU_KERNEL_STACK equ 48
+PERCPU_USER_STACK equ 56
+
+section .text
+bits 64
+  align 16
+  syscall_entry:
+    ;swap in the kernel gs base and move to the thread's kernel stack
+    swapgs
+    mov [gs:PERCPU_USER_STACK], rsp
+    mov rsp, [gs:PERCPU_KERNEL_STACK]
+
+    ;build a SyscallFrame, see syscall.rs
+    push qword [gs:PERCPU_USER_STACK]
+    push r11
+    push rcx
+    push r9
+    push r8
+    push r10
+    push rdx
+    push rsi
+    push rdi
+    push rax
+
+    ;the frame is saved, so the call may be interrupted or switched out
+    sti
+    cld
+
+    mov rdi, rsp        ;pointer to the SyscallFrame
+
+    extern syscall_handler
+    call syscall_handler
+
+    cli
+
+    ;everything but rax (the result), rcx and r11 is as the caller left it
+    pop rax
+    pop rdi
+    pop rsi
+    pop rdx
+    pop r10
+    pop r8
+    pop r9
+    pop rcx
+    pop r11
+
+    ;give the user gs base back, then return to the user stack
+    swapgs
+    pop rsp
+    o64 sysret
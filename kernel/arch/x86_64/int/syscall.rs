@@ -0,0 +1,72 @@
+//syscall.rs
+//the SYSCALL/SYSRET entry into the kernel, and the int 0x80 fallback
+
+use arch::x86_64::msr;
+use arch::x86_64::gdt;
+use arch::x86_64::int::int;
+use arch::x86_64::int::isr::ExceptionContext;
+use syscall;
+
+//vector of the int 0x80 fallback, which can be raised from ring 3
+pub const SYSCALL_VECTOR: u8 = 0x80;
+
+//RFLAGS bits cleared on entry: trap, interrupt, direction and alignment check
+const SYSCALL_FMASK: u64 = 1 << 8 | 1 << 9 | 1 << 10 | 1 << 18;
+
+extern "C" {
+    fn syscall_entry();
+}
+
+//registers saved by syscall_entry, lowest address first
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SyscallFrame {
+    //the system call number on entry, the result on return
+    pub rax: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub r10: u64,
+    pub r8: u64,
+    pub r9: u64,
+    //user instruction pointer and flags, saved by the CPU
+    pub rcx: u64,
+    pub r11: u64,
+    pub user_stack: u64
+}
+
+//enable SYSCALL on the running CPU. every CPU has its own copy of the MSRs
+pub fn init() {
+    //SYSRET loads cs from the STAR selector + 16 and ss from it + 8, which
+    //the GDT puts at user code and user data
+    let star = ((gdt::USER_DATA_SELECTOR - 8) as u64) << 48 |
+        (gdt::KERNEL_CODE_SELECTOR as u64) << 32;
+
+    unsafe {
+        msr::wrmsr(msr::IA32_STAR, star);
+        msr::wrmsr(msr::IA32_LSTAR, syscall_entry as u64);
+        msr::wrmsr(msr::IA32_FMASK, SYSCALL_FMASK);
+
+        let efer = msr::rdmsr(msr::IA32_EFER);
+        msr::wrmsr(msr::IA32_EFER, efer | msr::EFER_SCE);
+    }
+}
+
+//called from syscall_entry, on the thread's kernel stack with interrupts enabled
+#[no_mangle]
+pub extern "C" fn syscall_handler(frame: &mut SyscallFrame) {
+    let args = [frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9];
+    frame.rax = syscall::dispatch(frame.rax, args) as u64;
+}
+
+//vector 0x80, takes the same registers as SYSCALL. slower, but the saved
+//context can be dumped when debugging
+pub fn int80_handler(ctx: &mut ExceptionContext) {
+    //entered through an interrupt gate, the call may block
+    int::enable();
+
+    let args = [ctx.rdi, ctx.rsi, ctx.rdx, ctx.r10, ctx.r8, ctx.r9];
+    ctx.rax = syscall::dispatch(ctx.rax, args) as u64;
+
+    int::disable();
+}
@@ -0,0 +1,185 @@
+// area_frame_allocator.rs
+// Physical frame allocator, built from the Multiboot2 memory map
+
+use core::{cmp, ptr, slice};
+use multiboot2::{MemoryArea, MemoryAreaIter};
+use arch::x86_64::mem::PhysicalAddress;
+use arch::x86_64::mem::frame::{PageFrame, FrameAllocator, PAGE_SIZE};
+
+// Memory below 1 MiB is left alone, it holds firmware data and the AP
+// startup trampoline
+const LOW_MEMORY_END: PhysicalAddress = 0x100000;
+
+// The boot page tables identity map the first GiB, and the free bitmap is
+// written before the kernel is remapped
+const BOOT_MAPPED_END: PhysicalAddress = 0x40000000;
+
+// Hands out frames from the available memory areas, skipping the kernel image,
+// the Multiboot information structure and the free bitmap. Freed frames are
+// marked in the bitmap, which has a bit for every frame up to the end of the
+// highest area, and are handed out again before any new frames are taken from
+// the areas.
+pub struct AreaFrameAllocator {
+    next_free_frame: PageFrame,
+    current_area: Option<&'static MemoryArea>,
+    areas: MemoryAreaIter,
+    kernel_start: PageFrame,
+    kernel_end: PageFrame,
+    multiboot_start: PageFrame,
+    multiboot_end: PageFrame,
+    bitmap_start: PageFrame,
+    bitmap_end: PageFrame,
+    free_bitmap: &'static mut [u64],
+    free_count: usize,
+    // No word before this one has a bit set
+    free_hint: usize,
+}
+
+// The memory map lives in the Multiboot information structure, which is
+// never modified after boot
+unsafe impl Send for AreaFrameAllocator {}
+
+impl AreaFrameAllocator {
+    pub fn new(kernel_start: PhysicalAddress, kernel_end: PhysicalAddress,
+               multiboot_start: PhysicalAddress, multiboot_end: PhysicalAddress,
+               memory_areas: MemoryAreaIter) -> AreaFrameAllocator {
+        let last_frame = memory_areas.clone()
+            .map(|area| area_last_frame(area).number)
+            .max().expect("no memory areas");
+        let words = (last_frame + 1 + 63) / 64;
+        let bitmap_frames = (words * 8 + PAGE_SIZE as usize - 1) / PAGE_SIZE as usize;
+
+        // The bitmap goes in the first free frames past both the kernel image
+        // and the Multiboot structure, so it overlaps neither
+        let after = cmp::max(kernel_end, multiboot_end);
+        let bitmap_start = find_room(memory_areas.clone(), PageFrame::containing_address(after).number + 1,
+            bitmap_frames).expect("no room for the free frame bitmap");
+        let bitmap_end = PageFrame { number: bitmap_start.number + bitmap_frames - 1 };
+        assert!(bitmap_end.start() < BOOT_MAPPED_END, "free frame bitmap is not identity mapped");
+
+        let free_bitmap = unsafe {
+            ptr::write_bytes(bitmap_start.start() as *mut u64, 0, words);
+            slice::from_raw_parts_mut(bitmap_start.start() as *mut u64, words)
+        };
+
+        let mut allocator = AreaFrameAllocator {
+            next_free_frame: PageFrame::containing_address(LOW_MEMORY_END),
+            current_area: None,
+            areas: memory_areas,
+            kernel_start: PageFrame::containing_address(kernel_start),
+            kernel_end: PageFrame::containing_address(kernel_end),
+            multiboot_start: PageFrame::containing_address(multiboot_start),
+            multiboot_end: PageFrame::containing_address(multiboot_end),
+            bitmap_start: bitmap_start,
+            bitmap_end: bitmap_end,
+            free_bitmap: free_bitmap,
+            free_count: 0,
+            free_hint: 0,
+        };
+
+        allocator.choose_next_area();
+        allocator
+    }
+
+    // Number of frames currently held for reuse
+    pub fn free_count(&self) -> usize {
+        self.free_count
+    }
+
+    // Physical start and end of the free bitmap, which must stay identity
+    // mapped once the kernel is remapped
+    pub fn bitmap_range(&self) -> (PhysicalAddress, PhysicalAddress) {
+        (self.bitmap_start.start(), self.bitmap_end.start() + PAGE_SIZE as usize)
+    }
+
+    // Move on to the area with the lowest base address that still contains free frames
+    fn choose_next_area(&mut self) {
+        let next_free_frame = self.next_free_frame.number;
+
+        self.current_area = self.areas.clone().filter(|area| {
+            let address = area.get_base_addr() + area.get_length() - 1;
+            PageFrame::containing_address(address as usize).number >= next_free_frame
+        }).min_by_key(|area| area.get_base_addr());
+
+        if let Some(area) = self.current_area {
+            let start_frame = PageFrame::containing_address(area.get_base_addr() as usize);
+
+            if self.next_free_frame < start_frame {
+                self.next_free_frame = start_frame;
+            }
+        }
+    }
+
+    fn allocate_from_areas(&mut self) -> Option<PageFrame> {
+        loop {
+            let area = match self.current_area {
+                Some(area) => area,
+                // Out of memory
+                None => return None,
+            };
+
+            let frame = PageFrame { number: self.next_free_frame.number };
+            let current_area_last_frame = area_last_frame(area);
+
+            if frame > current_area_last_frame {
+                // All frames of the current area are used, switch to the next one
+                self.choose_next_area();
+            } else if frame >= self.kernel_start && frame <= self.kernel_end {
+                // Frame is used by the kernel image
+                self.next_free_frame = PageFrame { number: self.kernel_end.number + 1 };
+            } else if frame >= self.multiboot_start && frame <= self.multiboot_end {
+                // Frame is used by the Multiboot information structure
+                self.next_free_frame = PageFrame { number: self.multiboot_end.number + 1 };
+            } else if frame >= self.bitmap_start && frame <= self.bitmap_end {
+                // Frame holds the free bitmap
+                self.next_free_frame = PageFrame { number: self.bitmap_end.number + 1 };
+            } else {
+                // Frame is unused, claim it
+                self.next_free_frame.number += 1;
+                return Some(frame);
+            }
+        }
+    }
+}
+
+impl FrameAllocator for AreaFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PageFrame> {
+        if self.free_count > 0 {
+            while self.free_bitmap[self.free_hint] == 0 {
+                self.free_hint += 1;
+            }
+
+            let word = &mut self.free_bitmap[self.free_hint];
+            let bit = word.trailing_zeros() as usize;
+            *word &= !(1 << bit);
+            self.free_count -= 1;
+            return Some(PageFrame { number: self.free_hint * 64 + bit });
+        }
+
+        self.allocate_from_areas()
+    }
+
+    fn deallocate_frame(&mut self, frame: PageFrame) {
+        let (index, bit) = (frame.number / 64, frame.number % 64);
+        assert!(index < self.free_bitmap.len(), "frame {:#X} is outside of memory", frame.start());
+        assert!(self.free_bitmap[index] & (1 << bit) == 0, "frame {:#X} freed twice", frame.start());
+
+        self.free_bitmap[index] |= 1 << bit;
+        self.free_count += 1;
+        self.free_hint = cmp::min(self.free_hint, index);
+    }
+}
+
+fn area_last_frame(area: &MemoryArea) -> PageFrame {
+    let address = area.get_base_addr() + area.get_length() - 1;
+    PageFrame::containing_address(address as usize)
+}
+
+// Lowest run of the given number of frames inside one memory area, starting
+// no lower than the given frame number
+fn find_room(areas: MemoryAreaIter, lowest: usize, frames: usize) -> Option<PageFrame> {
+    areas.filter_map(|area| {
+        let first = cmp::max(lowest, PageFrame::containing_address(area.get_base_addr() as usize).number);
+        if first + frames - 1 <= area_last_frame(area).number { Some(first) } else { None }
+    }).min().map(|number| PageFrame { number: number })
+}
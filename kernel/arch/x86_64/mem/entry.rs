@@ -1,6 +1,8 @@
 // entry.rs
 // Contains structs and methods to represent page table entries
 
+use multiboot2::{ElfSection, ElfSectionFlags};
+use arch::x86_64::mem::PhysicalAddress;
 use arch::x86_64::mem::frame::PageFrame;
 
 bitflags! {
@@ -18,6 +20,25 @@ bitflags! {
     }
 }
 
+impl EntryFlags {
+    // Derive page permissions from the flags of a kernel ELF section
+    pub fn from_elf_section_flags(section: &ElfSection) -> EntryFlags {
+        let mut flags = EntryFlags::empty();
+
+        if section.get_flags().contains(ElfSectionFlags::ALLOCATED) {
+            flags = flags | EntryFlags::PRESENT;
+        }
+        if section.get_flags().contains(ElfSectionFlags::WRITABLE) {
+            flags = flags | EntryFlags::WRITABLE;
+        }
+        if !section.get_flags().contains(ElfSectionFlags::EXECUTABLE) {
+            flags = flags | EntryFlags::NO_EXECUTE;
+        }
+
+        flags
+    }
+}
+
 // Entry contains 64-bit flag
 pub struct Entry(u64);
 
@@ -34,6 +55,16 @@ impl Entry {
         EntryFlags::from_bits_truncate(self.0)
     }
 
+    // Raw 64-bit value of the entry
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    // Physical address stored in the entry, whether or not it is present
+    pub fn address(&self) -> PhysicalAddress {
+        self.0 as usize & 0x000fffff_fffff000
+    }
+
     // Get address from page in entry, if present
     pub fn pointed_frame(&self) -> Option<PageFrame> {
         if self.flags().contains(EntryFlags::PRESENT) {
@@ -49,5 +80,15 @@ impl Entry {
         assert!(frame.start() & !0x000fffff_fffff000 == 0);
         self.0 = frame.start() as u64 | flags.bits();
     }
+
+    // Point at the same frame as another entry, with the same flags
+    pub fn copy_from(&mut self, other: &Entry) {
+        self.0 = other.0;
+    }
+
+    // Add flags to the entry, keeping its frame and existing flags
+    pub fn insert_flags(&mut self, flags: EntryFlags) {
+        self.0 |= flags.bits();
+    }
 }
 
@@ -20,13 +20,45 @@ impl PageFrame {
     pub fn start(&self) -> PhysicalAddress {
 	self.number * PAGE_SIZE as usize
     }
+
+    pub fn range_inclusive(start: PageFrame, end: PageFrame) -> PageFrameIter {
+        PageFrameIter {
+            start: start,
+            end: end,
+        }
+    }
+
+    // Frames are deliberately not Copy, so that one cannot be freed twice by accident
+    pub fn clone(&self) -> PageFrame {
+        PageFrame { number: self.number }
+    }
+}
+
+pub struct PageFrameIter {
+    start: PageFrame,
+    end: PageFrame,
+}
+
+impl Iterator for PageFrameIter {
+    type Item = PageFrame;
+
+    fn next(&mut self) -> Option<PageFrame> {
+        if self.start <= self.end {
+            let frame = self.start.clone();
+            self.start.number += 1;
+            Some(frame)
+        } else {
+            None
+        }
+    }
 }
 
 pub trait FrameAllocator {
-    fn allocate_frame(&self) -> Option<PageFrame>;
-    fn deallocate_frame(&self, frame: PageFrame);
+    fn allocate_frame(&mut self) -> Option<PageFrame>;
+    fn deallocate_frame(&mut self, frame: PageFrame);
 }
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Page(usize);
 
 impl Page {
@@ -39,10 +71,17 @@ impl Page {
         Page (address / PAGE_SIZE as usize)
     }
 
-    fn start(&self) -> usize {
+    pub fn start(&self) -> VirtualAddress {
         self.0 * PAGE_SIZE as usize
     }
 
+    pub fn range_inclusive(start: Page, end: Page) -> PageIter {
+        PageIter {
+            start: start,
+            end: end,
+        }
+    }
+
     pub fn p4_index(&self) -> usize {
         (self.0 >> 27) & 0o777
     }
@@ -60,3 +99,23 @@ impl Page {
     }
 }
 
+
+#[derive(Clone)]
+pub struct PageIter {
+    start: Page,
+    end: Page,
+}
+
+impl Iterator for PageIter {
+    type Item = Page;
+
+    fn next(&mut self) -> Option<Page> {
+        if self.start <= self.end {
+            let page = self.start;
+            self.start.0 += 1;
+            Some(page)
+        } else {
+            None
+        }
+    }
+}
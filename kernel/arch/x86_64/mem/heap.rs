@@ -0,0 +1,260 @@
+// heap.rs
+// Kernel heap, and the global allocator backing the `alloc` crate
+// Small requests are served by slab caches, anything else by the linked list heap
+
+use core::mem;
+use core::ptr;
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt::Write;
+use spin::Mutex;
+use driver::vga;
+use driver::vga::Writer;
+use arch::x86_64::mem::{ActivePageTable, VirtualAddress};
+use arch::x86_64::mem::entry::EntryFlags;
+use arch::x86_64::mem::frame::{Page, FrameAllocator};
+use arch::x86_64::mem::slab;
+use arch::x86_64::int::int;
+use arch::x86_64::mem::slab::{SlabAllocator, CacheStats};
+
+// Virtual region reserved for the kernel heap
+pub const HEAP_START: VirtualAddress = 0x0000_0100_0000_0000;
+pub const HEAP_SIZE: usize = 1024 * 1024; // 1 MiB
+
+#[global_allocator]
+static HEAP_ALLOCATOR: KernelAllocator = KernelAllocator::empty();
+
+// Map the heap region to fresh frames, and hand it to the global allocator
+pub fn init<A>(active_table: &mut ActivePageTable, allocator: &mut A)
+        where A: FrameAllocator {
+    let heap_start_page = Page::containing_address(HEAP_START);
+    let heap_end_page = Page::containing_address(HEAP_START + HEAP_SIZE - 1);
+
+    for page in Page::range_inclusive(heap_start_page, heap_end_page) {
+        active_table.map(page, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE, allocator)
+            .expect("out of memory");
+    }
+
+    unsafe {
+        HEAP_ALLOCATOR.heap.init(HEAP_START, HEAP_SIZE);
+    }
+
+    vga::okay();
+    write!(Writer::new(), "Initialised kernel heap at {:#X}, size {} KiB\n",
+        HEAP_START, HEAP_SIZE / 1024).expect("Unexpected failure in write!()");
+}
+
+// Statistics for each of the general purpose slab size classes
+pub fn slab_stats() -> [CacheStats; 8] {
+    HEAP_ALLOCATOR.slabs.lock().stats()
+}
+
+pub struct KernelAllocator {
+    slabs: Mutex<SlabAllocator>,
+    heap: LockedHeap,
+}
+
+impl KernelAllocator {
+    pub const fn empty() -> KernelAllocator {
+        KernelAllocator {
+            slabs: Mutex::new(SlabAllocator::new()),
+            heap: LockedHeap::empty(),
+        }
+    }
+}
+
+// Slab pages are mapped through the memory controller, so nothing may
+// allocate from the heap while holding the memory controller lock
+unsafe impl GlobalAlloc for KernelAllocator {
+    // Interrupts are off while the locks are held, so neither a handler nor
+    // a thread switch can find them taken
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        int::without_interrupts(|| {
+            let ptr = self.slabs.lock().allocate(layout);
+
+            if ptr.is_null() {
+                self.heap.alloc(layout)
+            } else {
+                ptr
+            }
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        int::without_interrupts(|| {
+            if slab::contains(ptr as usize) {
+                self.slabs.lock().deallocate(ptr, layout)
+            } else {
+                self.heap.dealloc(ptr, layout)
+            }
+        })
+    }
+}
+
+// Header written at the start of every free region
+struct ListNode {
+    size: usize,
+    next: *mut ListNode,
+}
+
+// First-fit allocator, keeping free regions in a list sorted by address so
+// neighbouring regions can be merged when memory is returned
+pub struct Heap {
+    head: *mut ListNode,
+    size: usize,
+    used: usize,
+}
+
+unsafe impl Send for Heap {}
+
+impl Heap {
+    pub const fn empty() -> Heap {
+        Heap {
+            head: 0 as *mut ListNode,
+            size: 0,
+            used: 0,
+        }
+    }
+
+    // The region must be mapped, unused, and never handed out elsewhere
+    pub unsafe fn init(&mut self, start: VirtualAddress, size: usize) {
+        self.size = size;
+        self.add_free_region(start, size);
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Heap::size_align(layout);
+
+        unsafe {
+            let mut previous: *mut ListNode = ptr::null_mut();
+            let mut current = self.head;
+
+            while !current.is_null() {
+                let region_start = current as usize;
+                let region_end = region_start + (*current).size;
+                let alloc_start = align_up(region_start, align);
+                let alloc_end = alloc_start.saturating_add(size);
+
+                if alloc_end <= region_end {
+                    let front = alloc_start - region_start;
+                    let back = region_end - alloc_end;
+
+                    // Leftovers must be big enough to hold a list node
+                    if (front == 0 || front >= mem::size_of::<ListNode>()) &&
+                            (back == 0 || back >= mem::size_of::<ListNode>()) {
+                        let next = (*current).next;
+                        if previous.is_null() {
+                            self.head = next;
+                        } else {
+                            (*previous).next = next;
+                        }
+
+                        if front > 0 {
+                            self.add_free_region(region_start, front);
+                        }
+                        if back > 0 {
+                            self.add_free_region(alloc_end, back);
+                        }
+
+                        self.used += size;
+                        return alloc_start as *mut u8;
+                    }
+                }
+
+                previous = current;
+                current = (*current).next;
+            }
+        }
+
+        ptr::null_mut()
+    }
+
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Heap::size_align(layout);
+
+        self.add_free_region(ptr as usize, size);
+        self.used -= size;
+    }
+
+    // Insert a region into the free list, merging it with adjacent regions
+    unsafe fn add_free_region(&mut self, address: usize, size: usize) {
+        assert!(align_up(address, mem::align_of::<ListNode>()) == address);
+        assert!(size >= mem::size_of::<ListNode>());
+
+        let mut previous: *mut ListNode = ptr::null_mut();
+        let mut current = self.head;
+
+        while !current.is_null() && (current as usize) < address {
+            previous = current;
+            current = (*current).next;
+        }
+
+        let node = address as *mut ListNode;
+        (*node).size = size;
+        (*node).next = current;
+
+        if !current.is_null() && address + size == current as usize {
+            (*node).size += (*current).size;
+            (*node).next = (*current).next;
+        }
+
+        if previous.is_null() {
+            self.head = node;
+        } else if previous as usize + (*previous).size == address {
+            (*previous).size += (*node).size;
+            (*previous).next = (*node).next;
+        } else {
+            (*previous).next = node;
+        }
+    }
+
+    // Every allocation must be able to hold a list node once it is freed
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let align = if layout.align() > mem::align_of::<ListNode>() {
+            layout.align()
+        } else {
+            mem::align_of::<ListNode>()
+        };
+        let size = if layout.size() > mem::size_of::<ListNode>() {
+            layout.size()
+        } else {
+            mem::size_of::<ListNode>()
+        };
+
+        (align_up(size, mem::align_of::<ListNode>()), align)
+    }
+}
+
+pub struct LockedHeap(Mutex<Heap>);
+
+impl LockedHeap {
+    pub const fn empty() -> LockedHeap {
+        LockedHeap(Mutex::new(Heap::empty()))
+    }
+
+    pub unsafe fn init(&self, start: VirtualAddress, size: usize) {
+        self.0.lock().init(start, size);
+    }
+}
+
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().deallocate(ptr, layout)
+    }
+}
+
+// Round an address up to the given power-of-two alignment
+pub fn align_up(address: usize, align: usize) -> usize {
+    (address + align - 1) & !(align - 1)
+}
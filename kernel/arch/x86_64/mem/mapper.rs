@@ -0,0 +1,164 @@
+// mapper.rs
+// Creates and removes page mappings through the recursively mapped P4 table
+
+use core::ptr::NonNull;
+use arch::x86_64::mem::entry::EntryFlags;
+use arch::x86_64::mem::frame::{Page, PageFrame, FrameAllocator};
+use arch::x86_64::mem::ENTRY_COUNT;
+use arch::x86_64::mem::table::{Table, Level4, P4};
+use arch::x86_64::mem::tlb;
+
+// Why a page could not be mapped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    // No free frame for the page or for a table on the way to it
+    OutOfMemory,
+}
+
+pub struct Mapper {
+    p4: NonNull<Table<Level4>>,
+}
+
+impl Mapper {
+    // Only one Mapper may exist for the active P4 table at a time
+    pub unsafe fn new() -> Mapper {
+        Mapper {
+            p4: NonNull::new_unchecked(P4),
+        }
+    }
+
+    pub fn p4(&self) -> &Table<Level4> {
+        unsafe { self.p4.as_ref() }
+    }
+
+    pub fn p4_mut(&mut self) -> &mut Table<Level4> {
+        unsafe { self.p4.as_mut() }
+    }
+
+    // Map a page to a given frame, creating any missing tables on the way.
+    // With USER_ACCESSIBLE in flags the page can be reached from ring 3.
+    // Tables already created are kept if a later one cannot be
+    pub fn map_to<A>(&mut self, page: Page, frame: PageFrame, flags: EntryFlags, allocator: &mut A)
+            -> Result<(), MapError> where A: FrameAllocator {
+        let user = flags.contains(EntryFlags::USER_ACCESSIBLE);
+        {
+            let p3 = self.p4_mut().next_table_create(page.p4_index(), user, allocator)
+                .ok_or(MapError::OutOfMemory)?;
+            let p2 = p3.next_table_create(page.p3_index(), user, allocator)
+                .ok_or(MapError::OutOfMemory)?;
+            let p1 = p2.next_table_create(page.p2_index(), user, allocator)
+                .ok_or(MapError::OutOfMemory)?;
+
+            assert!(p1[page.p1_index()].is_unused(),
+                "page 0x{:x} is already mapped", page.start());
+            p1[page.p1_index()].set(frame, flags | EntryFlags::PRESENT);
+        }
+
+        tlb::flush(page.start());
+        Ok(())
+    }
+
+    // Map a page to any free frame
+    pub fn map<A>(&mut self, page: Page, flags: EntryFlags, allocator: &mut A)
+            -> Result<(), MapError> where A: FrameAllocator {
+        let frame = allocator.allocate_frame().ok_or(MapError::OutOfMemory)?;
+        let result = self.map_to(page, frame.clone(), flags, allocator);
+        if result.is_err() {
+            allocator.deallocate_frame(frame);
+        }
+        result
+    }
+
+    // Map a frame to the page with the same address
+    pub fn identity_map<A>(&mut self, frame: PageFrame, flags: EntryFlags, allocator: &mut A)
+            -> Result<(), MapError> where A: FrameAllocator {
+        let page = Page::containing_address(frame.start());
+        self.map_to(page, frame, flags, allocator)
+    }
+
+    // Change the flags of a mapped page, keeping its frame
+    pub fn set_flags(&mut self, page: Page, flags: EntryFlags) {
+        {
+            let p1 = self.p4_mut()
+                .next_table_as_mut(page.p4_index())
+                .and_then(|p3| p3.next_table_as_mut(page.p3_index()))
+                .and_then(|p2| p2.next_table_as_mut(page.p2_index()))
+                .expect("mapping code does not support huge pages");
+
+            let frame = p1[page.p1_index()].pointed_frame()
+                .expect("page is not mapped");
+            p1[page.p1_index()].set(frame, flags | EntryFlags::PRESENT);
+        }
+
+        tlb::flush(page.start());
+    }
+
+    // Remove the mapping for a page, and return its frame to the allocator
+    pub fn unmap<A>(&mut self, page: Page, allocator: &mut A)
+            where A: FrameAllocator {
+        let frame = {
+            let p1 = self.p4_mut()
+                .next_table_as_mut(page.p4_index())
+                .and_then(|p3| p3.next_table_as_mut(page.p3_index()))
+                .and_then(|p2| p2.next_table_as_mut(page.p2_index()))
+                .expect("mapping code does not support huge pages");
+
+            let frame = p1[page.p1_index()].pointed_frame()
+                .expect("page is not mapped");
+            p1[page.p1_index()].set_as_unused();
+            frame
+        };
+
+        tlb::flush(page.start());
+        // TODO: free P1/P2/P3 tables once they are empty
+        allocator.deallocate_frame(frame);
+    }
+
+    // Free every page mapped through the P4 entries from first up to the
+    // recursive entry, along with the tables that map them. Used to tear
+    // down the user part of a page table, which holds no huge pages
+    pub fn free_from<A>(&mut self, first: usize, allocator: &mut A)
+            where A: FrameAllocator {
+        for p4_index in first..ENTRY_COUNT - 1 {
+            let p3_frame = match self.p4()[p4_index].pointed_frame() {
+                Some(frame) => frame,
+                None => continue,
+            };
+
+            {
+                let p3 = self.p4_mut().next_table_as_mut(p4_index).unwrap();
+                for p3_index in 0..ENTRY_COUNT {
+                    let p2_frame = match p3[p3_index].pointed_frame() {
+                        Some(frame) => frame,
+                        None => continue,
+                    };
+
+                    {
+                        let p2 = p3.next_table_as_mut(p3_index).unwrap();
+                        for p2_index in 0..ENTRY_COUNT {
+                            let p1_frame = match p2[p2_index].pointed_frame() {
+                                Some(frame) => frame,
+                                None => continue,
+                            };
+
+                            {
+                                let p1 = p2.next_table_as_mut(p2_index).unwrap();
+                                for p1_index in 0..ENTRY_COUNT {
+                                    if let Some(frame) = p1[p1_index].pointed_frame() {
+                                        allocator.deallocate_frame(frame);
+                                    }
+                                }
+                            }
+                            allocator.deallocate_frame(p1_frame);
+                        }
+                    }
+                    allocator.deallocate_frame(p2_frame);
+                }
+            }
+            allocator.deallocate_frame(p3_frame);
+            self.p4_mut()[p4_index].set_as_unused();
+        }
+
+        tlb::flush_all();
+    }
+}
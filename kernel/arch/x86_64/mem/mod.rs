@@ -1,60 +1,386 @@
 pub mod frame;
 pub mod entry;
 pub mod table;
+pub mod area_frame_allocator;
+pub mod mapper;
+pub mod tlb;
+pub mod temporary_page;
+pub mod heap;
+pub mod slab;
+pub mod walk;
+pub mod stack;
 
-use arch::x86_64::mem::frame::{Page, PageFrame, PAGE_SIZE};
+pub use self::walk::{walk, PageWalk, PageSize};
+
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::fmt::Write;
+use multiboot2::BootInformation;
+use spin::Mutex;
+use driver::vga;
+use driver::vga::Writer;
+use arch::x86_64::{control, msr};
+use arch::x86_64::int::int;
+
+use arch::x86_64::mem::frame::{Page, PageFrame, FrameAllocator, PAGE_SIZE};
 use arch::x86_64::mem::entry::EntryFlags;
-use arch::x86_64::mem::table::P4;
+use arch::x86_64::mem::table::{Table, Level1};
+use arch::x86_64::mem::mapper::{Mapper, MapError};
+use arch::x86_64::mem::temporary_page::TemporaryPage;
+use arch::x86_64::mem::area_frame_allocator::AreaFrameAllocator;
+use arch::x86_64::mem::stack::{Stack, StackAllocator};
 
 const ENTRY_COUNT: usize = 512;
 
+// Scratch page used while editing inactive page tables
+pub const TEMPORARY_PAGE: VirtualAddress = 0x0000_0080_0000_0000;
+
+// The kernel only maps memory through the P4 entries below this one, which
+// every page table shares. User memory lies above it, and is private to the
+// page table of each process
+pub const KERNEL_P4_ENTRIES: usize = 32;
+
+// Physical address of the VGA text buffer
+const VGA_BUFFER: PhysicalAddress = 0xB8000;
+
 pub type PhysicalAddress = usize;
 pub type VirtualAddress = usize;
 
-pub fn translate(virtual_address: VirtualAddress) -> Option<PhysicalAddress> {
-    let offset = virtual_address / PAGE_SIZE as usize;
-
-    translate_page(frame::Page::containing_address(virtual_address))
-        .map(|frame| frame.number * PAGE_SIZE as usize * offset) 
-}
-
-fn translate_page(page: Page) -> Option<PageFrame> {
-    let p3 = unsafe { &*P4 }.next_table(page.p4_index());
-
-    let huge_page = || {
-            p3.and_then(|p3| {
-            let p3_entry = &p3[page.p3_index()];
-            // 1GiB page?
-            if let Some(start_frame) = p3_entry.pointed_frame() {
-                if p3_entry.flags().contains(EntryFlags::HUGE_PAGE) {
-                    // address must be 1GiB aligned
-                    assert!(start_frame.number % (ENTRY_COUNT * ENTRY_COUNT) == 0);
-                    return Some(PageFrame {
-                        number: start_frame.number + page.p2_index() *
-                                ENTRY_COUNT + page.p1_index(),
-                    });
-                }
+// Owns the active page table and the frame allocator once boot-time setup is
+// done, so that the rest of the kernel can map memory on demand
+pub struct MemoryController {
+    pub active_table: ActivePageTable,
+    pub frame_allocator: AreaFrameAllocator,
+    pub stack_allocator: StackAllocator,
+    temporary_page: TemporaryPage,
+}
+
+impl MemoryController {
+    pub fn alloc_stack(&mut self, name: &'static str, size_in_pages: usize) -> Option<Stack> {
+        self.stack_allocator.alloc_stack(name, &mut self.active_table,
+            &mut self.frame_allocator, size_in_pages)
+    }
+
+    pub fn free_stack(&mut self, stack: Stack) {
+        self.stack_allocator.free_stack(stack, &mut self.active_table, &mut self.frame_allocator);
+    }
+
+    pub fn map_page(&mut self, page: Page, flags: EntryFlags) -> Result<(), MapError> {
+        self.active_table.map(page, flags, &mut self.frame_allocator)
+    }
+
+    pub fn unmap_page(&mut self, page: Page) {
+        self.active_table.unmap(page, &mut self.frame_allocator);
+    }
+
+    pub fn set_page_flags(&mut self, page: Page, flags: EntryFlags) {
+        self.active_table.set_flags(page, flags);
+    }
+
+    // A page table for a user process, with no user memory yet. It points
+    // at the same kernel P3 tables as the active one, so the kernel is
+    // mapped the same in both
+    pub fn new_user_table(&mut self) -> InactivePageTable {
+        let frame = self.frame_allocator.allocate_frame().expect("out of memory");
+        let table = InactivePageTable::new(frame.clone(), &mut self.active_table, &mut self.temporary_page);
+
+        {
+            let p4 = self.temporary_page.map_table_frame(frame, &mut self.active_table);
+            for index in 0..KERNEL_P4_ENTRIES {
+                p4[index].copy_from(&self.active_table.p4()[index]);
             }
-            if let Some(p2) = p3.next_table(page.p3_index()) {
-                let p2_entry = &p2[page.p2_index()];
-                // 2MiB page?
-                if let Some(start_frame) = p2_entry.pointed_frame() {
-                    if p2_entry.flags().contains(EntryFlags::HUGE_PAGE) {
-                        // address must be 2MiB aligned
-                        assert!(start_frame.number % ENTRY_COUNT == 0);
-                        return Some(PageFrame {
-                            number: start_frame.number + page.p1_index()
-                        });
-                    }
-                }
+        }
+        self.temporary_page.unmap(&mut self.active_table);
+
+        table
+    }
+
+    // Free a user page table made by new_user_table, along with all the
+    // user memory mapped in it. It must not be loaded on any CPU
+    pub fn free_user_table(&mut self, mut table: InactivePageTable) {
+        assert!(active_page_table() != table.p4_address(), "cannot free the active page table");
+
+        {
+            let allocator = &mut self.frame_allocator;
+            self.active_table.with(&mut table, &mut self.temporary_page, |mapper| {
+                mapper.free_from(KERNEL_P4_ENTRIES, allocator);
+            });
+        }
+
+        self.frame_allocator.deallocate_frame(table.p4_frame);
+    }
+}
+
+pub static MEMORY_CONTROLLER: Mutex<Option<MemoryController>> = Mutex::new(None);
+
+// Physical address of the kernel's P4 table, loaded while no process runs
+static KERNEL_TABLE: AtomicUsize = AtomicUsize::new(0);
+
+pub fn init_controller(mut active_table: ActivePageTable, mut frame_allocator: AreaFrameAllocator) {
+    stack::guard_boot_stack(&mut active_table, &mut frame_allocator);
+
+    // Create every kernel P3 table up front. Page tables made later copy
+    // the kernel P4 entries once, so these must never change afterwards
+    for index in 0..KERNEL_P4_ENTRIES {
+        active_table.p4_mut().next_table_create(index, false, &mut frame_allocator)
+            .expect("out of memory");
+    }
+    KERNEL_TABLE.store(active_page_table(), Ordering::SeqCst);
+
+    let temporary_page = TemporaryPage::new(Page::containing_address(TEMPORARY_PAGE), &mut frame_allocator);
+
+    *MEMORY_CONTROLLER.lock() = Some(MemoryController {
+        active_table: active_table,
+        frame_allocator: frame_allocator,
+        stack_allocator: StackAllocator::new(),
+        temporary_page: temporary_page,
+    });
+}
+
+// Physical address of the P4 table in CR3
+pub fn active_page_table() -> PhysicalAddress {
+    control::read_cr3() as usize & 0x000fffff_fffff000
+}
+
+pub fn kernel_page_table() -> PhysicalAddress {
+    KERNEL_TABLE.load(Ordering::Relaxed)
+}
+
+// Load the page table with the given P4, unless it is already active
+pub fn load_page_table(p4: PhysicalAddress) {
+    if active_page_table() != p4 {
+        unsafe { control::write_cr3(p4 as u64); }
+    }
+}
+
+// A new, empty page table for a user process
+pub fn new_user_table() -> InactivePageTable {
+    with_controller(|controller| controller.new_user_table())
+}
+
+pub fn free_user_table(table: InactivePageTable) {
+    with_controller(|controller| controller.free_user_table(table))
+}
+
+// Identity map a physical range that lies outside of RAM handed out by the
+// frame allocator, such as firmware tables or device registers. Pages that
+// are already mapped are left alone.
+pub fn identity_map_range(start: PhysicalAddress, size: usize, flags: EntryFlags) {
+    with_controller(|controller| {
+        let start_frame = PageFrame::containing_address(start);
+        let end_frame = PageFrame::containing_address(start + size - 1);
+
+        for frame in PageFrame::range_inclusive(start_frame, end_frame) {
+            if translate(frame.start()).is_none() {
+                controller.active_table.identity_map(frame, flags, &mut controller.frame_allocator)
+                    .expect("out of memory");
             }
-            None
-        })
+        }
+    })
+}
+
+// Run a closure against the memory controller. Interrupts are off while it
+// is locked, so a thread is never switched out while holding it.
+pub fn with_controller<F, R>(f: F) -> R where F: FnOnce(&mut MemoryController) -> R {
+    int::without_interrupts(|| {
+        f(MEMORY_CONTROLLER.lock().as_mut().expect("memory controller not initialised"))
+    })
+}
+
+// Allocate a kernel stack through the memory controller
+pub fn alloc_stack(name: &'static str, size_in_pages: usize) -> Option<Stack> {
+    with_controller(|controller| controller.alloc_stack(name, size_in_pages))
+}
+
+// Release a kernel stack through the memory controller
+pub fn free_stack(stack: Stack) {
+    with_controller(|controller| controller.free_stack(stack))
+}
+
+// The page table currently loaded in CR3, accessed through the recursive mapping
+pub struct ActivePageTable {
+    mapper: Mapper,
+}
+
+// Access is serialised through MEMORY_CONTROLLER
+unsafe impl Send for ActivePageTable {}
+
+impl Deref for ActivePageTable {
+    type Target = Mapper;
+
+    fn deref(&self) -> &Mapper {
+        &self.mapper
+    }
+}
+
+impl DerefMut for ActivePageTable {
+    fn deref_mut(&mut self) -> &mut Mapper {
+        &mut self.mapper
+    }
+}
+
+impl ActivePageTable {
+    // Only one ActivePageTable may exist at a time
+    pub unsafe fn new() -> ActivePageTable {
+        ActivePageTable {
+            mapper: Mapper::new(),
+        }
+    }
+
+    // Run a closure against an inactive table, by temporarily pointing the
+    // recursive entry of the active P4 at it
+    pub fn with<F>(&mut self, table: &mut InactivePageTable, temporary_page: &mut TemporaryPage, f: F)
+            where F: FnOnce(&mut Mapper) {
+        {
+            let backup = PageFrame::containing_address(control::read_cr3() as usize);
+
+            // Keep the active P4 reachable, so the recursive entry can be restored
+            let p4_table = temporary_page.map_table_frame(backup.clone(), self);
+
+            self.p4_mut()[511].set(table.p4_frame.clone(), EntryFlags::PRESENT | EntryFlags::WRITABLE);
+            tlb::flush_all();
+
+            f(self);
+
+            p4_table[511].set(backup, EntryFlags::PRESENT | EntryFlags::WRITABLE);
+            tlb::flush_all();
+        }
+
+        temporary_page.unmap(self);
+    }
+
+    // Load a new P4 table into CR3, returning the previously active one
+    pub fn switch(&mut self, new_table: InactivePageTable) -> InactivePageTable {
+        let old_table = InactivePageTable {
+            p4_frame: PageFrame::containing_address(control::read_cr3() as usize),
+        };
+
+        unsafe {
+            control::write_cr3(new_table.p4_frame.start() as u64);
+        }
+
+        old_table
+    }
+}
+
+// A page table that is not loaded in CR3
+pub struct InactivePageTable {
+    p4_frame: PageFrame,
+}
+
+impl InactivePageTable {
+    // Physical address of the P4 table, as loaded into CR3
+    pub fn p4_address(&self) -> PhysicalAddress {
+        self.p4_frame.start()
+    }
+
+    // Zero a new P4 table and recursively map it to itself
+    pub fn new(frame: PageFrame, active_table: &mut ActivePageTable,
+               temporary_page: &mut TemporaryPage) -> InactivePageTable {
+        {
+            let table: &mut Table<Level1> = temporary_page.map_table_frame(frame.clone(), active_table);
+            table.clear();
+            table[511].set(frame.clone(), EntryFlags::PRESENT | EntryFlags::WRITABLE);
+        }
+        temporary_page.unmap(active_table);
+
+        InactivePageTable { p4_frame: frame }
+    }
+}
+
+// Allow the NO_EXECUTE bit in page table entries
+fn enable_nxe_bit() {
+    unsafe {
+        let efer = msr::rdmsr(msr::IA32_EFER);
+        msr::wrmsr(msr::IA32_EFER, efer | msr::EFER_NXE);
+    }
+}
+
+// Make ring 0 writes to read-only pages fault
+fn enable_write_protect_bit() {
+    unsafe {
+        control::write_cr0(control::read_cr0() | control::CR0_WRITE_PROTECT);
+    }
+}
+
+// Replace the boot page tables with a new P4 that maps each kernel ELF
+// section with its own permissions, instead of one writable, executable GiB
+pub fn remap_kernel(allocator: &mut AreaFrameAllocator, mb_info: &BootInformation,
+                    multiboot_start: PhysicalAddress, multiboot_end: PhysicalAddress)
+        -> ActivePageTable {
+    let (bitmap_start, bitmap_end) = allocator.bitmap_range();
+
+    enable_nxe_bit();
+    enable_write_protect_bit();
+
+    let mut temporary_page = TemporaryPage::new(Page::containing_address(TEMPORARY_PAGE), allocator);
+    let mut active_table = unsafe { ActivePageTable::new() };
+
+    let mut new_table = {
+        let frame = allocator.allocate_frame().expect("no more frames");
+        InactivePageTable::new(frame, &mut active_table, &mut temporary_page)
     };
 
-    p3.and_then(|p3| p3.next_table(page.p3_index()))
-      .and_then(|p2| p2.next_table(page.p2_index()))
-      .and_then(|p1| p1[page.p1_index()].pointed_frame())
-      .or_else(huge_page)
+    active_table.with(&mut new_table, &mut temporary_page, |mapper| {
+        let elf_sections_tag = mb_info.get_elf_sections()
+            .expect("ELF sections tag required!");
+
+        for section in elf_sections_tag.get_sections() {
+            if !section.is_allocated() {
+                // Section is not loaded into memory
+                continue;
+            }
+
+            assert!(section.get_start_addr() as usize % PAGE_SIZE as usize == 0,
+                "kernel sections need to be page aligned");
+
+            let flags = EntryFlags::from_elf_section_flags(&section);
+            let start_frame = PageFrame::containing_address(section.get_start_addr() as usize);
+            let end_frame = PageFrame::containing_address(
+                (section.get_start_addr() + section.get_size() - 1) as usize);
+
+            for frame in PageFrame::range_inclusive(start_frame, end_frame) {
+                mapper.identity_map(frame, flags, allocator).expect("out of memory");
+            }
+        }
+
+        // VGA text buffer
+        let vga_buffer_frame = PageFrame::containing_address(VGA_BUFFER);
+        mapper.identity_map(vga_buffer_frame, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE, allocator)
+            .expect("out of memory");
+
+        // Multiboot information structure
+        let multiboot_start = PageFrame::containing_address(multiboot_start);
+        let multiboot_end = PageFrame::containing_address(multiboot_end - 1);
+        for frame in PageFrame::range_inclusive(multiboot_start, multiboot_end) {
+            mapper.identity_map(frame, EntryFlags::PRESENT | EntryFlags::NO_EXECUTE, allocator)
+                .expect("out of memory");
+        }
+
+        // Free frame bitmap, which the allocator keeps using at the same address
+        let bitmap_start = PageFrame::containing_address(bitmap_start);
+        let bitmap_end = PageFrame::containing_address(bitmap_end - 1);
+        for frame in PageFrame::range_inclusive(bitmap_start, bitmap_end) {
+            mapper.identity_map(frame, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE, allocator)
+                .expect("out of memory");
+        }
+    });
+
+    let old_table = active_table.switch(new_table);
+
+    vga::okay();
+    write!(Writer::new(), "Remapped kernel sections, old P4 table at {:#X}\n",
+        old_table.p4_frame.start()).expect("Unexpected failure in write!()");
+
+    active_table
 }
 
+// Translate a virtual address through the active page tables, following
+// 4 KiB, 2 MiB and 1 GiB mappings alike
+pub fn translate(virtual_address: VirtualAddress) -> Option<PhysicalAddress> {
+    walk(virtual_address).physical
+}
+
+pub fn translate_page(page: Page) -> Option<PageFrame> {
+    translate(page.start()).map(PageFrame::containing_address)
+}
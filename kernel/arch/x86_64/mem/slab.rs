@@ -0,0 +1,198 @@
+// slab.rs
+// Slab caches for small, fixed-size kernel objects
+
+use core::ptr;
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use arch::x86_64::mem::{MEMORY_CONTROLLER, VirtualAddress};
+use arch::x86_64::mem::entry::EntryFlags;
+use arch::x86_64::mem::frame::{Page, PAGE_SIZE};
+
+// Virtual region that slab pages are mapped into
+pub const SLAB_START: VirtualAddress = 0x0000_0180_0000_0000;
+pub const SLAB_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+
+// Object sizes served by the general purpose size classes
+pub const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+// Only advanced with the memory controller locked, once a page is mapped
+static NEXT_SLAB_PAGE: AtomicUsize = AtomicUsize::new(SLAB_START);
+
+// Map a fresh page into the slab region, backed by a frame from the frame allocator.
+// Fails until the memory controller has been installed, once the region is
+// used up, or when there are no free frames, so the heap can take over.
+//
+// Slab pages are never given back. The free objects of a page are spread
+// through its cache's free list, with no count kept per page, so an empty
+// page cannot be found without walking the whole list. The caches only
+// hold small kernel objects and stop growing once the region is full
+fn allocate_slab_page() -> Option<VirtualAddress> {
+    let mut controller = MEMORY_CONTROLLER.lock();
+    let controller = controller.as_mut()?;
+
+    let address = NEXT_SLAB_PAGE.load(Ordering::SeqCst);
+    if address >= SLAB_START + SLAB_SIZE {
+        return None;
+    }
+
+    controller.map_page(Page::containing_address(address),
+        EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE).ok()?;
+    NEXT_SLAB_PAGE.store(address + PAGE_SIZE as usize, Ordering::SeqCst);
+    Some(address)
+}
+
+pub fn contains(address: VirtualAddress) -> bool {
+    address >= SLAB_START && address < SLAB_START + SLAB_SIZE
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub objects_in_use: usize,
+    pub objects_free: usize,
+    pub pages_held: usize,
+}
+
+// Written into every free object, linking it to the next free one
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+// A cache of equally sized objects, carved out of whole pages
+pub struct SlabCache {
+    name: &'static str,
+    object_size: usize,
+    free_list: *mut FreeObject,
+    objects_in_use: usize,
+    objects_free: usize,
+    pages_held: usize,
+}
+
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    // Object size must be a power of two between 16 bytes and one page,
+    // so that every object is naturally aligned to its own size
+    pub const fn new(name: &'static str, object_size: usize) -> SlabCache {
+        SlabCache {
+            name: name,
+            object_size: object_size,
+            free_list: 0 as *mut FreeObject,
+            objects_in_use: 0,
+            objects_free: 0,
+            pages_held: 0,
+        }
+    }
+
+    pub fn object_size(&self) -> usize {
+        self.object_size
+    }
+
+    pub fn allocate(&mut self) -> *mut u8 {
+        if self.free_list.is_null() && !self.grow() {
+            return ptr::null_mut();
+        }
+
+        unsafe {
+            let object = self.free_list;
+            self.free_list = (*object).next;
+            self.objects_free -= 1;
+            self.objects_in_use += 1;
+            object as *mut u8
+        }
+    }
+
+    // The pointer must have been returned by `allocate` on this cache
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8) {
+        let object = ptr as *mut FreeObject;
+        (*object).next = self.free_list;
+        self.free_list = object;
+        self.objects_in_use -= 1;
+        self.objects_free += 1;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            name: self.name,
+            object_size: self.object_size,
+            objects_in_use: self.objects_in_use,
+            objects_free: self.objects_free,
+            pages_held: self.pages_held,
+        }
+    }
+
+    // Take a new page and split it into free objects
+    fn grow(&mut self) -> bool {
+        assert!(self.object_size.is_power_of_two() && self.object_size >= 16
+            && self.object_size <= PAGE_SIZE as usize, "invalid slab object size");
+
+        let page = match allocate_slab_page() {
+            Some(page) => page,
+            None => return false,
+        };
+
+        let count = PAGE_SIZE as usize / self.object_size;
+        for i in (0..count).rev() {
+            unsafe {
+                let object = (page + i * self.object_size) as *mut FreeObject;
+                (*object).next = self.free_list;
+                self.free_list = object;
+            }
+        }
+
+        self.objects_free += count;
+        self.pages_held += 1;
+        true
+    }
+}
+
+// A set of power-of-two size classes, for general purpose allocations
+pub struct SlabAllocator {
+    caches: [SlabCache; 8],
+}
+
+impl SlabAllocator {
+    pub const fn new() -> SlabAllocator {
+        SlabAllocator {
+            caches: [
+                SlabCache::new("size-16", 16),
+                SlabCache::new("size-32", 32),
+                SlabCache::new("size-64", 64),
+                SlabCache::new("size-128", 128),
+                SlabCache::new("size-256", 256),
+                SlabCache::new("size-512", 512),
+                SlabCache::new("size-1024", 1024),
+                SlabCache::new("size-2048", 2048),
+            ],
+        }
+    }
+
+    // Find the smallest size class that satisfies both size and alignment
+    fn cache_index(layout: &Layout) -> Option<usize> {
+        let required = if layout.size() > layout.align() { layout.size() } else { layout.align() };
+        SIZE_CLASSES.iter().position(|&size| size >= required)
+    }
+
+    // Null if the layout is too big for a size class, or no page could be mapped
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        match SlabAllocator::cache_index(&layout) {
+            Some(index) => self.caches[index].allocate(),
+            None => ptr::null_mut(),
+        }
+    }
+
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let index = SlabAllocator::cache_index(&layout)
+            .expect("deallocating an object that does not belong to a slab");
+        self.caches[index].deallocate(ptr);
+    }
+
+    pub fn stats(&self) -> [CacheStats; 8] {
+        let mut stats = [self.caches[0].stats(); 8];
+        for (i, cache) in self.caches.iter().enumerate() {
+            stats[i] = cache.stats();
+        }
+        stats
+    }
+}
@@ -0,0 +1,207 @@
+// stack.rs
+// Kernel stacks, each allocated with an unmapped guard page below it
+
+use core::fmt::Write;
+use spin::Mutex;
+use driver::vga;
+use driver::vga::Writer;
+use arch::x86_64::mem::{ActivePageTable, VirtualAddress};
+use arch::x86_64::mem::entry::EntryFlags;
+use arch::x86_64::mem::frame::{Page, PageIter, FrameAllocator, PAGE_SIZE};
+
+// Virtual region that kernel stacks are allocated from
+pub const STACK_START: VirtualAddress = 0x0000_0200_0000_0000;
+pub const STACK_AREA_SIZE: usize = 256 * 1024 * 1024; // 256 MiB
+
+// Maximum number of stacks tracked for overflow reporting
+const MAX_STACKS: usize = 64;
+
+// Boot stack reserved in boot.asm
+extern "C" {
+    static stack_bottom: u8;
+    static stack_top: u8;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stack {
+    name: &'static str,
+    top: VirtualAddress,
+    bottom: VirtualAddress,
+}
+
+impl Stack {
+    fn new(name: &'static str, top: VirtualAddress, bottom: VirtualAddress) -> Stack {
+        assert!(top > bottom);
+        Stack {
+            name: name,
+            top: top,
+            bottom: bottom,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // Stacks grow downwards, so this is the initial stack pointer
+    pub fn top(&self) -> VirtualAddress {
+        self.top
+    }
+
+    pub fn bottom(&self) -> VirtualAddress {
+        self.bottom
+    }
+
+    // Start of the unmapped page directly below the stack
+    pub fn guard_page(&self) -> VirtualAddress {
+        self.bottom - PAGE_SIZE as usize
+    }
+
+    pub fn size_in_pages(&self) -> usize {
+        (self.top - self.bottom) / PAGE_SIZE as usize
+    }
+
+    fn pages(&self) -> PageIter {
+        Page::range_inclusive(Page::containing_address(self.bottom),
+            Page::containing_address(self.top - 1))
+    }
+}
+
+// Every stack with a guard page, so page faults can name the stack that overflowed
+static STACKS: Mutex<[Option<Stack>; MAX_STACKS]> = Mutex::new([None; MAX_STACKS]);
+
+fn register(stack: Stack) {
+    let mut stacks = STACKS.lock();
+
+    match stacks.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => *slot = Some(stack),
+        None => {
+            vga::error();
+            write!(Writer::new(), "Too many stacks, overflows of \"{}\" will not be reported\n",
+                stack.name()).expect("Unexpected failure in write!()");
+        }
+    }
+}
+
+fn unregister(stack: &Stack) {
+    let mut stacks = STACKS.lock();
+
+    if let Some(slot) = stacks.iter_mut()
+            .find(|slot| slot.map_or(false, |s| s.bottom == stack.bottom)) {
+        *slot = None;
+    }
+}
+
+// Find the stack whose guard page contains an address. Called from fault
+// handlers, so it gives up rather than spin on a lock held by the faulting code.
+pub fn find_overflowed(address: VirtualAddress) -> Option<Stack> {
+    let stacks = STACKS.try_lock()?;
+    let guard = Page::containing_address(address).start();
+
+    stacks.iter()
+        .filter_map(|slot| *slot)
+        .find(|stack| stack.guard_page() == guard)
+}
+
+pub struct StackAllocator {
+    range: PageIter,
+    // Address ranges of freed stacks, reused for stacks of the same size.
+    // A fixed array, since the heap may itself need the memory controller.
+    free: [Option<Stack>; MAX_STACKS],
+}
+
+impl StackAllocator {
+    pub fn new() -> StackAllocator {
+        let start = Page::containing_address(STACK_START);
+        let end = Page::containing_address(STACK_START + STACK_AREA_SIZE - 1);
+
+        StackAllocator {
+            range: Page::range_inclusive(start, end),
+            free: [None; MAX_STACKS],
+        }
+    }
+
+    // Map a stack of the given size, leaving the page below it unmapped
+    pub fn alloc_stack<A>(&mut self, name: &'static str, active_table: &mut ActivePageTable,
+                          frame_allocator: &mut A, size_in_pages: usize) -> Option<Stack>
+            where A: FrameAllocator {
+        if size_in_pages == 0 {
+            return None;
+        }
+
+        let reusable = self.free.iter_mut()
+            .find(|slot| slot.map_or(false, |stack| stack.size_in_pages() == size_in_pages));
+        if let Some(slot) = reusable {
+            let stack = Stack { name: name, ..slot.take().unwrap() };
+
+            for page in stack.pages() {
+                active_table.map(page, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+                    frame_allocator).expect("out of memory");
+            }
+
+            register(stack);
+            return Some(stack);
+        }
+
+        let mut range = self.range.clone();
+
+        let guard_page = range.next();
+        let stack_start = range.next();
+        let stack_end = if size_in_pages == 1 {
+            stack_start
+        } else {
+            range.nth(size_in_pages - 2)
+        };
+
+        match (guard_page, stack_start, stack_end) {
+            (Some(_), Some(start), Some(end)) => {
+                self.range = range;
+
+                for page in Page::range_inclusive(start, end) {
+                    active_table.map(page, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+                        frame_allocator).expect("out of memory");
+                }
+
+                let stack = Stack::new(name, end.start() + PAGE_SIZE as usize, start.start());
+                register(stack);
+                Some(stack)
+            }
+            // Stack area exhausted
+            _ => None,
+        }
+    }
+
+    // Unmap a stack and release its frames. The stack must not be in use.
+    pub fn free_stack<A>(&mut self, stack: Stack, active_table: &mut ActivePageTable,
+                         frame_allocator: &mut A)
+            where A: FrameAllocator {
+        unregister(&stack);
+
+        for page in stack.pages() {
+            active_table.unmap(page, frame_allocator);
+        }
+
+        // With no free slot left, the address range is simply not reused
+        if let Some(slot) = self.free.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(stack);
+        }
+    }
+}
+
+// The boot stack sits directly above the boot page tables in .bss. Those
+// tables are unused once the kernel is remapped, so unmap the page below the
+// stack to turn it into a guard page.
+pub fn guard_boot_stack<A>(active_table: &mut ActivePageTable, frame_allocator: &mut A)
+        where A: FrameAllocator {
+    let (bottom, top) = unsafe {
+        (&stack_bottom as *const u8 as usize, &stack_top as *const u8 as usize)
+    };
+
+    let stack = Stack::new("boot", top, bottom);
+    active_table.unmap(Page::containing_address(stack.guard_page()), frame_allocator);
+    register(stack);
+
+    vga::okay();
+    write!(Writer::new(), "Boot stack guard page at {:#X}\n", stack.guard_page())
+        .expect("Unexpected failure in write!()");
+}
@@ -3,7 +3,7 @@
 
 use core::marker::PhantomData;
 use core::ops::{ Index, IndexMut };
-use arch::x86_64::mem::{ entry::*, frame::PageFrame };
+use arch::x86_64::mem::{ entry::*, frame::{PageFrame, FrameAllocator} };
 use arch::x86_64::mem::ENTRY_COUNT;
 
 pub const P4: *mut Table<Level4> = 0xffffffff_fffff000 as *mut _;
@@ -78,6 +78,28 @@ impl<L> Table<L> where L: MappedLevel {
             .map(|address| unsafe {&mut *(address as *mut _)})
     }
 
+    // Get the next table, creating and zeroing it if it doesn't exist yet,
+    // or none if there is no frame for it.
+    // A page is only reachable from ring 3 if every table on the way to it is
+    // user accessible too, so user mappings mark the entry as such
+    pub fn next_table_create<A>(&mut self, index: usize, user: bool, allocator: &mut A)
+            -> Option<&mut Table<L::NextLevel>> where A: FrameAllocator {
+        if self.next_table(index).is_none() {
+            assert!(!self.entries[index].flags().contains(EntryFlags::HUGE_PAGE),
+                "mapping code does not support huge pages");
+
+            let frame = allocator.allocate_frame()?;
+            self.entries[index].set(frame, EntryFlags::PRESENT | EntryFlags::WRITABLE);
+            self.next_table_as_mut(index).unwrap().clear();
+        }
+
+        if user {
+            self.entries[index].insert_flags(EntryFlags::USER_ACCESSIBLE);
+        }
+
+        self.next_table_as_mut(index)
+    }
+
     fn next_table_address(&self, index: usize) -> Option<usize> {
         let entry_flags = self[index].flags();
         
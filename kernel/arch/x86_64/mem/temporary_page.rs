@@ -0,0 +1,72 @@
+// temporary_page.rs
+// A single scratch page, used to edit page tables that are not currently active
+
+use arch::x86_64::mem::{ActivePageTable, VirtualAddress};
+use arch::x86_64::mem::entry::EntryFlags;
+use arch::x86_64::mem::frame::{Page, PageFrame, FrameAllocator};
+use arch::x86_64::mem::table::{Table, Level1};
+
+pub struct TemporaryPage {
+    page: Page,
+    allocator: TinyAllocator,
+}
+
+impl TemporaryPage {
+    pub fn new<A>(page: Page, allocator: &mut A) -> TemporaryPage
+            where A: FrameAllocator {
+        TemporaryPage {
+            page: page,
+            allocator: TinyAllocator::new(allocator),
+        }
+    }
+
+    // Map the temporary page to the given frame, returning its virtual address
+    pub fn map(&mut self, frame: PageFrame, active_table: &mut ActivePageTable) -> VirtualAddress {
+        active_table.map_to(self.page, frame, EntryFlags::WRITABLE, &mut self.allocator)
+            .expect("temporary page allocator ran out of frames");
+        self.page.start()
+    }
+
+    // Map the temporary page to a frame holding a page table
+    pub fn map_table_frame(&mut self, frame: PageFrame, active_table: &mut ActivePageTable)
+            -> &mut Table<Level1> {
+        unsafe { &mut *(self.map(frame, active_table) as *mut Table<Level1>) }
+    }
+
+    pub fn unmap(&mut self, active_table: &mut ActivePageTable) {
+        active_table.unmap(self.page, &mut self.allocator);
+    }
+}
+
+// Holds just enough frames to create the P3, P2 and P1 tables for the temporary page
+struct TinyAllocator([Option<PageFrame>; 3]);
+
+impl TinyAllocator {
+    fn new<A>(allocator: &mut A) -> TinyAllocator
+            where A: FrameAllocator {
+        let mut f = || allocator.allocate_frame();
+        let frames = [f(), f(), f()];
+        TinyAllocator(frames)
+    }
+}
+
+impl FrameAllocator for TinyAllocator {
+    fn allocate_frame(&mut self) -> Option<PageFrame> {
+        for frame_option in &mut self.0 {
+            if frame_option.is_some() {
+                return frame_option.take();
+            }
+        }
+        None
+    }
+
+    fn deallocate_frame(&mut self, frame: PageFrame) {
+        for frame_option in &mut self.0 {
+            if frame_option.is_none() {
+                *frame_option = Some(frame);
+                return;
+            }
+        }
+        panic!("Tiny allocator can hold only 3 frames.");
+    }
+}
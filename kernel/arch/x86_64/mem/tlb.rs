@@ -0,0 +1,19 @@
+// tlb.rs
+// Translation lookaside buffer management
+
+use arch::x86_64::mem::VirtualAddress;
+use arch::x86_64::control;
+
+// Invalidate the TLB entry for a single page
+pub fn flush(address: VirtualAddress) {
+    unsafe {
+        asm!("invlpg ($0)" :: "r" (address) : "memory");
+    }
+}
+
+// Invalidate all non-global TLB entries, by reloading CR3
+pub fn flush_all() {
+    unsafe {
+        control::write_cr3(control::read_cr3());
+    }
+}
@@ -0,0 +1,184 @@
+// walk.rs
+// Walks the active page tables for a virtual address, recording every level
+
+use core::fmt;
+use arch::x86_64::mem::{PhysicalAddress, VirtualAddress};
+use arch::x86_64::mem::entry::{Entry, EntryFlags};
+use arch::x86_64::mem::frame::Page;
+use arch::x86_64::mem::table::P4;
+
+const SIZE_4KIB: usize = 0x1000;
+const SIZE_2MIB: usize = 0x20_0000;
+const SIZE_1GIB: usize = 0x4000_0000;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PageSize {
+    Size4KiB,
+    Size2MiB,
+    Size1GiB,
+}
+
+impl PageSize {
+    pub fn bytes(&self) -> usize {
+        match *self {
+            PageSize::Size4KiB => SIZE_4KIB,
+            PageSize::Size2MiB => SIZE_2MIB,
+            PageSize::Size1GiB => SIZE_1GIB,
+        }
+    }
+}
+
+// The entry found at one level of the walk
+#[derive(Debug, Clone, Copy)]
+pub struct WalkLevel {
+    pub level: u8,
+    pub index: usize,
+    pub value: u64,
+    pub flags: EntryFlags,
+}
+
+impl WalkLevel {
+    fn new(level: u8, index: usize, entry: &Entry) -> WalkLevel {
+        WalkLevel {
+            level: level,
+            index: index,
+            value: entry.value(),
+            flags: entry.flags(),
+        }
+    }
+}
+
+// Result of a page table walk, from P4 down to the last level reached
+#[derive(Debug, Clone, Copy)]
+pub struct PageWalk {
+    pub address: VirtualAddress,
+    pub canonical: bool,
+    // Indexed P4, P3, P2, P1; None for levels the walk never reached
+    pub levels: [Option<WalkLevel>; 4],
+    pub page_size: Option<PageSize>,
+    pub physical: Option<PhysicalAddress>,
+}
+
+impl PageWalk {
+    pub fn is_mapped(&self) -> bool {
+        self.physical.is_some()
+    }
+
+    // Deepest entry the walk looked at
+    pub fn last_level(&self) -> Option<&WalkLevel> {
+        self.levels.iter().filter_map(|level| level.as_ref()).last()
+    }
+
+    // Combined permissions along the walk: a page is only writable, user
+    // accessible or executable if every level allows it
+    pub fn effective_flags(&self) -> Option<EntryFlags> {
+        if !self.is_mapped() {
+            return None;
+        }
+
+        let mut flags = EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::USER_ACCESSIBLE;
+        let mut no_execute = false;
+
+        for level in self.levels.iter().filter_map(|level| level.as_ref()) {
+            flags = flags & level.flags;
+            no_execute = no_execute || level.flags.contains(EntryFlags::NO_EXECUTE);
+        }
+
+        if no_execute {
+            flags = flags | EntryFlags::NO_EXECUTE;
+        }
+        Some(flags)
+    }
+}
+
+impl fmt::Display for PageWalk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Page walk for {:#X}:\n", self.address)?;
+
+        if !self.canonical {
+            return write!(f, "  address is not canonical\n");
+        }
+
+        for level in self.levels.iter().filter_map(|level| level.as_ref()) {
+            write!(f, "  P{}[{:3}] = {:#018X} {:?}\n",
+                level.level, level.index, level.value, level.flags)?;
+        }
+
+        match (self.physical, self.page_size) {
+            (Some(physical), Some(size)) =>
+                write!(f, "  -> {:#X} ({:?} page)\n", physical, size),
+            _ => match self.last_level() {
+                Some(level) => write!(f, "  not mapped: P{} entry is not present\n", level.level),
+                None => write!(f, "  not mapped\n"),
+            },
+        }
+    }
+}
+
+pub fn is_canonical(address: VirtualAddress) -> bool {
+    address < 0x0000_8000_0000_0000 || address >= 0xffff_8000_0000_0000
+}
+
+// Walk the active page tables for an address. This never faults, so it is
+// safe to call from exception handlers.
+pub fn walk(address: VirtualAddress) -> PageWalk {
+    let mut walk = PageWalk {
+        address: address,
+        canonical: is_canonical(address),
+        levels: [None; 4],
+        page_size: None,
+        physical: None,
+    };
+
+    if !walk.canonical {
+        return walk;
+    }
+
+    let page = Page::containing_address(address);
+
+    let p4 = unsafe { &*P4 };
+    walk.levels[0] = Some(WalkLevel::new(4, page.p4_index(), &p4[page.p4_index()]));
+
+    let p3 = match p4.next_table(page.p4_index()) {
+        Some(p3) => p3,
+        None => return walk,
+    };
+    let p3_entry = &p3[page.p3_index()];
+    walk.levels[1] = Some(WalkLevel::new(3, page.p3_index(), p3_entry));
+
+    // 1GiB page?
+    if p3_entry.flags().contains(EntryFlags::PRESENT | EntryFlags::HUGE_PAGE) {
+        walk.page_size = Some(PageSize::Size1GiB);
+        // bit 12 of a huge page entry is PAT, not part of the address
+        walk.physical = Some((p3_entry.address() & !(SIZE_1GIB - 1)) + (address & (SIZE_1GIB - 1)));
+        return walk;
+    }
+
+    let p2 = match p3.next_table(page.p3_index()) {
+        Some(p2) => p2,
+        None => return walk,
+    };
+    let p2_entry = &p2[page.p2_index()];
+    walk.levels[2] = Some(WalkLevel::new(2, page.p2_index(), p2_entry));
+
+    // 2MiB page?
+    if p2_entry.flags().contains(EntryFlags::PRESENT | EntryFlags::HUGE_PAGE) {
+        walk.page_size = Some(PageSize::Size2MiB);
+        walk.physical = Some((p2_entry.address() & !(SIZE_2MIB - 1)) + (address & (SIZE_2MIB - 1)));
+        return walk;
+    }
+
+    let p1 = match p2.next_table(page.p2_index()) {
+        Some(p1) => p1,
+        None => return walk,
+    };
+    let p1_entry = &p1[page.p1_index()];
+    walk.levels[3] = Some(WalkLevel::new(1, page.p1_index(), p1_entry));
+
+    if p1_entry.flags().contains(EntryFlags::PRESENT) {
+        walk.page_size = Some(PageSize::Size4KiB);
+        walk.physical = Some(p1_entry.address() + (address & (SIZE_4KIB - 1)));
+    }
+
+    walk
+}
@@ -1,101 +1,129 @@
 #[macro_use]
+pub mod percpu;
+#[macro_use]
 pub mod idt;
 pub mod int;
 pub mod gdt;
+pub mod tss;
 pub mod mem;
+pub mod msr;
+pub mod control;
+pub mod cpuid;
+pub mod acpi;
+pub mod power;
+pub mod tsc;
+pub mod task;
+pub mod smp;
 
 extern "C" {
-    // Default handlers
-    fn isr_default();
-    fn isr_default_err();
-    
-    // Exceptions
-    fn divide_by_zero_wrapper();
-    fn debug_wrapper();
-    fn breakpoint_wrapper();
-    fn overflow_wrapper();
-    fn bounds_wrapper();
-    fn opcode_wrapper();
-    fn device_na_wrapper();
-    fn double_fault_wrapper();
-    fn gpf_wrapper();
-    fn x87_float_wrapper();
-    fn page_fault_wrapper();
-
-    // Interrupts
-    fn pit_wrapper();
-    fn keyboard_wrapper();
-    fn com1_wrapper();
-    fn isr_spurious();
+    // Entry stubs for all 256 vectors, see int/isr.asm
+    static isr_stub_table: [u64; 256];
 }
 
+//the bootstrap processor's TSS, filled in by gdt_init. the CPU reads it and
+//the kernel rewrites its stack pointers, so it is only reached through raw
+//pointers, never through references
+static mut TSS: tss::TaskStateSegment = tss::TaskStateSegment::new();
+
 lazy_static! {
-    static ref GDT: gdt::Gdt = {
-    	let mut gdt = gdt::Gdt::new();
-
-        //set access flags for code segments
-        let code_flags: u8 =
-            gdt::AccessFlags::ReadWrite as u8 |
-            gdt::AccessFlags::Executable as u8 |
-            gdt::AccessFlags::One as u8 |
-            gdt::AccessFlags::Present as u8;
-        //set access flags for data segments
-        let data_flags: u8 =
-            gdt::AccessFlags::ReadWrite as u8 |
-            gdt::AccessFlags::One as u8 |
-            gdt::AccessFlags::Present as u8;
-        //set granularity flags, indicate a 64-bit table
-        let granularity_flags: u8 =
-            gdt::GranularityFlags::Page as u8 |
-            gdt::GranularityFlags::LongMode64 as u8;
-
-        gdt.set_entry(0, gdt::GdtEntry::set_up(0, 0, 0, 0));
-        gdt.set_entry(1, gdt::GdtEntry::set_up(0, 0xFFFFF, code_flags, granularity_flags));
-        gdt.set_entry(2, gdt::GdtEntry::set_up(0, 0xFFFFF, data_flags, granularity_flags));
-
-        gdt
-    };
+    static ref GDT: gdt::Gdt = new_gdt(bsp_tss());
+}
+
+//each CPU has its own TSS and GDT
+pub fn new_tss() -> tss::TaskStateSegment {
+    let mut tss = tss::TaskStateSegment::new();
+
+    //give each critical exception its own stack, so it can still run when
+    //the interrupted stack has overflowed or is otherwise corrupt
+    let double_fault_stack = mem::alloc_stack("double fault", tss::IST_STACK_PAGES)
+        .expect("Could not allocate double fault stack");
+    let nmi_stack = mem::alloc_stack("NMI", tss::IST_STACK_PAGES)
+        .expect("Could not allocate NMI stack");
+    let machine_check_stack = mem::alloc_stack("machine check", tss::IST_STACK_PAGES)
+        .expect("Could not allocate machine check stack");
+
+    tss.interrupt_stack_table[tss::DOUBLE_FAULT_IST_INDEX] = double_fault_stack.top() as u64;
+    tss.interrupt_stack_table[tss::NMI_IST_INDEX] = nmi_stack.top() as u64;
+    tss.interrupt_stack_table[tss::MACHINE_CHECK_IST_INDEX] = machine_check_stack.top() as u64;
+
+    tss
+}
+
+pub fn new_gdt(tss: *const tss::TaskStateSegment) -> gdt::Gdt {
+    let mut gdt = gdt::Gdt::new();
+
+    //set access flags for code segments
+    let code_flags: u8 =
+        gdt::AccessFlags::ReadWrite as u8 |
+        gdt::AccessFlags::Executable as u8 |
+        gdt::AccessFlags::One as u8 |
+        gdt::AccessFlags::Present as u8;
+    //set access flags for data segments
+    let data_flags: u8 =
+        gdt::AccessFlags::ReadWrite as u8 |
+        gdt::AccessFlags::One as u8 |
+        gdt::AccessFlags::Present as u8;
+    //set granularity flags, indicate a 64-bit table
+    let granularity_flags: u8 =
+        gdt::GranularityFlags::Page as u8 |
+        gdt::GranularityFlags::LongMode64 as u8;
+
+    gdt.set_entry(0, gdt::GdtEntry::set_up(0, 0, 0, 0));
+    gdt.set_entry(1, gdt::GdtEntry::set_up(0, 0xFFFFF, code_flags, granularity_flags));
+    gdt.set_entry(2, gdt::GdtEntry::set_up(0, 0xFFFFF, data_flags, granularity_flags));
+    gdt.set_entry(3, gdt::GdtEntry::set_up(0, 0xFFFFF,
+        data_flags | gdt::AccessFlags::Ring3 as u8, granularity_flags));
+    gdt.set_entry(4, gdt::GdtEntry::set_up(0, 0xFFFFF,
+        code_flags | gdt::AccessFlags::Ring3 as u8, granularity_flags));
+    gdt.set_tss(5, tss);
+
+    gdt
 }
 
 lazy_static! {
     static ref IDT: idt::Idt = {
     	let mut idt = idt::Idt::new();
 
+        // Every vector enters through its stub, and is routed by int::isr::isr_dispatch
+        for (vector, &stub) in unsafe { isr_stub_table.iter() }.enumerate() {
+            idt.set_handler(vector as u8, stub);
+        }
+
         // Exceptions
-        idt.set_handler(0, divide_by_zero_wrapper as u64);
-        idt.set_handler(1, debug_wrapper as u64); 
-        idt.set_handler(3, breakpoint_wrapper as u64);
-        idt.set_handler(4, overflow_wrapper as u64);
-        idt.set_handler(5, bounds_wrapper as u64);
-        idt.set_handler(6, opcode_wrapper as u64);
-        idt.set_handler(7, device_na_wrapper as u64); 
-        idt.set_handler(8, double_fault_wrapper as u64);
-        idt.set_handler(10, isr_default_err as u64); // Invalid TSS
-        idt.set_handler(11, isr_default_err as u64); // Segment not present
-        idt.set_handler(12, isr_default_err as u64); // Stack segment fault
-        idt.set_handler(13, gpf_wrapper as u64);
-        idt.set_handler(14, page_fault_wrapper as u64);
-        idt.set_handler(16, x87_float_wrapper as u64);
-        idt.set_handler(17, isr_default_err as u64); // Alignment check
-        idt.set_handler(18, isr_default as u64); // Machine check
-        idt.set_handler(19, isr_default as u64); // SIMD flo{ating point
-        idt.set_handler(20, isr_default as u64); // Virtualisation fault
-        idt.set_handler(30, isr_default_err as u64); // Security exception
-
-        // Interrupts
-        idt.set_handler(32, pit_wrapper as u64);
-        idt.set_handler(33, keyboard_wrapper as u64);
-        idt.set_handler(36, com1_wrapper as u64);
-        idt.set_handler(39, isr_spurious as u64);
+        idt.options(2)
+            .set_stack_index(tss::NMI_IST_INDEX as u8);
+        idt.options(3)
+            .set_gate_type(idt::GateType::Trap)
+            .set_privilege_level(idt::PrivilegeLevel::Ring3);
+        idt.options(8)
+            .set_stack_index(tss::DOUBLE_FAULT_IST_INDEX as u8);
+        idt.options(18)
+            .set_stack_index(tss::MACHINE_CHECK_IST_INDEX as u8);
+
+        // System calls, for debugging without SYSCALL
+        idt.options(int::syscall::SYSCALL_VECTOR)
+            .set_privilege_level(idt::PrivilegeLevel::Ring3);
 
         idt
     };
 }
 
 pub fn gdt_init() {
+    unsafe { *bsp_tss() = new_tss(); }
     GDT.install();
+    tss::load(gdt::TSS_SELECTOR);
+}
+
+//the bootstrap processor's TSS, the APs each allocate their own
+pub fn bsp_tss() -> *mut tss::TaskStateSegment {
+    unsafe { &mut TSS as *mut tss::TaskStateSegment }
 }
 
 pub fn idt_init() {
     IDT.install();
 }
+
+//the IDT is shared by every CPU
+pub fn idt_load() {
+    IDT.load();
+}
@@ -0,0 +1,46 @@
+// msr.rs
+// Access to model-specific registers
+
+// Local APIC base address and enable bits
+pub const IA32_APIC_BASE: u32 = 0x1B;
+
+// Extended feature enable register
+pub const IA32_EFER: u32 = 0xC000_0080;
+
+// Segment bases, the kernel GS base is swapped in by swapgs
+pub const IA32_FS_BASE: u32 = 0xC000_0100;
+pub const IA32_GS_BASE: u32 = 0xC000_0101;
+pub const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+
+// System call target: segment bases, entry point and the RFLAGS bits to clear
+pub const IA32_STAR: u32 = 0xC000_0081;
+pub const IA32_LSTAR: u32 = 0xC000_0082;
+pub const IA32_FMASK: u32 = 0xC000_0084;
+
+// EFER bits
+pub const EFER_SCE: u64 = 1 << 0; // enable SYSCALL and SYSRET
+pub const EFER_NXE: u64 = 1 << 11; // enable the NO_EXECUTE page table bit
+
+#[inline(always)]
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+
+    asm!("rdmsr" :
+        "={eax}"(low), "={edx}"(high) : "{ecx}"(msr) :
+        "memory" :
+        "volatile");
+
+    ((high as u64) << 32) | (low as u64)
+}
+
+#[inline(always)]
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    let low = value as u32;
+    let high = (value >> 32) as u32;
+
+    asm!("wrmsr" ::
+        "{ecx}"(msr), "{eax}"(low), "{edx}"(high) :
+        "memory" :
+        "volatile");
+}
@@ -0,0 +1,177 @@
+// percpu.rs
+// Data private to each CPU, reached through the GS segment base
+
+//***************************************//
+// In the kernel IA32_GS_BASE points at  //
+// the running CPU's PerCpu, and         //
+// IA32_KERNEL_GS_BASE holds the user    //
+// value. swapgs exchanges the two on    //
+// every entry from and exit to ring 3   //
+//***************************************//
+
+use alloc::boxed::Box;
+use arch::x86_64::msr;
+use arch::x86_64::tss::TaskStateSegment;
+
+// current_thread when no thread is running on the CPU yet
+pub const NO_THREAD: usize = !0;
+
+// Every field is pointer sized, so the per_cpu! macro can read and update
+// any of them with a single gs-relative instruction, which an interrupt
+// cannot split and which cannot be moved to another CPU halfway
+#[repr(C)]
+pub struct PerCpu {
+    // Address of this structure, so a pointer to it is one load away
+    self_ptr: usize,
+    // 0 for the bootstrap processor, then in the order the APs were started
+    pub cpu_id: usize,
+    // Id of the thread running on this CPU, or NO_THREAD
+    pub current_thread: usize,
+    // Preemption is only allowed while this is zero
+    pub preempt_count: usize,
+    // Number of interrupt handlers running, counting nested ones
+    pub irq_depth: usize,
+    // This CPU's TSS, written through this pointer, see percpu::tss
+    pub tss: *mut TaskStateSegment,
+    // Top of the running thread's kernel stack, entered from ring 3
+    pub kernel_stack: usize,
+    // User stack pointer, saved here by the system call entry while it
+    // switches stacks. The offsets of this and kernel_stack are also used
+    // in int/syscall.asm, see offset
+    pub user_stack: usize,
+    // Set from interrupt handlers to ask for a thread switch, acted on
+    // once the interrupt has been acknowledged
+    pub need_resched: usize,
+}
+
+// Byte offset of each PerCpu field, named after the field, for per_cpu!
+// and int/syscall.asm. init checks them against the struct
+#[allow(non_upper_case_globals)]
+pub mod offset {
+    pub const self_ptr: usize = 0;
+    pub const cpu_id: usize = 8;
+    pub const current_thread: usize = 16;
+    pub const preempt_count: usize = 24;
+    pub const irq_depth: usize = 32;
+    pub const tss: usize = 40;
+    pub const kernel_stack: usize = 48;
+    pub const user_stack: usize = 56;
+    pub const need_resched: usize = 64;
+}
+
+// Read, write or adjust a field of the running CPU's PerCpu:
+//   per_cpu!(cpu_id), per_cpu!(current_thread = id), per_cpu!(irq_depth += 1)
+#[macro_export]
+macro_rules! per_cpu {
+    (@offset $field:ident) => {
+        $crate::arch::x86_64::percpu::offset::$field
+    };
+    ($field:ident) => {
+        $crate::arch::x86_64::percpu::read(per_cpu!(@offset $field))
+    };
+    ($field:ident = $value:expr) => {
+        $crate::arch::x86_64::percpu::write(per_cpu!(@offset $field), $value)
+    };
+    ($field:ident += $value:expr) => {
+        $crate::arch::x86_64::percpu::add(per_cpu!(@offset $field), $value)
+    };
+    ($field:ident -= $value:expr) => {
+        $crate::arch::x86_64::percpu::sub(per_cpu!(@offset $field), $value)
+    };
+}
+
+// Set up the PerCpu of the running CPU. Loading a selector into gs clears the
+// base, so this must come after the GDT is loaded.
+pub fn init(cpu_id: usize, tss: *mut TaskStateSegment) {
+    let area: &'static mut PerCpu = Box::leak(Box::new(PerCpu {
+        self_ptr: 0,
+        cpu_id: cpu_id,
+        current_thread: NO_THREAD,
+        preempt_count: 0,
+        irq_depth: 0,
+        tss: tss,
+        kernel_stack: 0,
+        user_stack: 0,
+        need_resched: 0,
+    }));
+    area.self_ptr = area as *mut PerCpu as usize;
+    check_offsets(area);
+
+    unsafe {
+        msr::wrmsr(msr::IA32_GS_BASE, area.self_ptr as u64);
+        msr::wrmsr(msr::IA32_KERNEL_GS_BASE, 0);
+    }
+}
+
+fn check_offsets(area: &PerCpu) {
+    let base = area as *const PerCpu as usize;
+    let offsets = [
+        (&area.self_ptr as *const _ as usize, offset::self_ptr),
+        (&area.cpu_id as *const _ as usize, offset::cpu_id),
+        (&area.current_thread as *const _ as usize, offset::current_thread),
+        (&area.preempt_count as *const _ as usize, offset::preempt_count),
+        (&area.irq_depth as *const _ as usize, offset::irq_depth),
+        (&area.tss as *const _ as usize, offset::tss),
+        (&area.kernel_stack as *const _ as usize, offset::kernel_stack),
+        (&area.user_stack as *const _ as usize, offset::user_stack),
+        (&area.need_resched as *const _ as usize, offset::need_resched),
+    ];
+
+    for &(address, offset) in offsets.iter() {
+        assert!(address - base == offset, "PerCpu field at {} is listed at offset {}",
+            address - base, offset);
+    }
+}
+
+// The running CPU's TSS. The CPU reads it on every privilege change, so it
+// is never borrowed, only written through this pointer
+pub fn tss() -> *mut TaskStateSegment {
+    let area = per_cpu!(self_ptr) as *const PerCpu;
+    unsafe { (*area).tss }
+}
+
+// Only reached through per_cpu!, which always passes the offset of a field
+#[inline(always)]
+pub fn read(offset: usize) -> usize {
+    let value: usize;
+    unsafe {
+        asm!("mov %gs:($1), $0" : "=r"(value) : "r"(offset) :: "volatile");
+    }
+    value
+}
+
+#[inline(always)]
+pub fn write(offset: usize, value: usize) {
+    unsafe {
+        asm!("mov $1, %gs:($0)" :: "r"(offset), "r"(value) : "memory" : "volatile");
+    }
+}
+
+#[inline(always)]
+pub fn add(offset: usize, value: usize) {
+    unsafe {
+        asm!("add $1, %gs:($0)" :: "r"(offset), "r"(value) : "memory", "cc" : "volatile");
+    }
+}
+
+#[inline(always)]
+pub fn sub(offset: usize, value: usize) {
+    unsafe {
+        asm!("sub $1, %gs:($0)" :: "r"(offset), "r"(value) : "memory", "cc" : "volatile");
+    }
+}
+
+// The running CPU's PerCpu. Only fields that cannot change under the caller,
+// such as cpu_id, should be read through it rather than through per_cpu!
+pub fn this_cpu() -> &'static PerCpu {
+    unsafe { &*(read(offset::self_ptr) as *const PerCpu) }
+}
+
+pub fn cpu_id() -> usize {
+    per_cpu!(cpu_id)
+}
+
+// Whether an interrupt handler is running on this CPU
+pub fn in_interrupt() -> bool {
+    per_cpu!(irq_depth) != 0
+}
@@ -0,0 +1,148 @@
+//power.rs
+//shutting down and rebooting the machine
+
+use core::ptr;
+use arch::dev::port_io;
+use arch::x86_64::acpi;
+use arch::x86_64::acpi::fadt::Fadt;
+use arch::x86_64::idt::IdtPointer;
+use arch::x86_64::int::int;
+use arch::x86_64::mem;
+use arch::x86_64::mem::entry::EntryFlags;
+#[cfg(feature = "qemu-exit")]
+use utils::qemu;
+
+//PM1 control register bits
+const PM1_SCI_EN: u16 = 1 << 0;
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_TYP_MASK: u16 = 0b111 << PM1_SLP_TYP_SHIFT;
+const PM1_SLP_EN: u16 = 1 << 13;
+
+//8042 keyboard controller
+const KBC_STATUS: u16 = 0x64;
+const KBC_COMMAND: u16 = 0x64;
+const KBC_INPUT_FULL: u8 = 1 << 1;
+const KBC_PULSE_RESET: u8 = 0xFE;
+
+//turn the machine off, through ACPI sleep state S5
+pub fn shutdown() -> ! {
+    int::disable();
+
+    //under test, the QEMU debug exit device ends the run with a status code
+    #[cfg(feature = "qemu-exit")]
+    qemu::shutdown();
+
+    if let Some(fadt) = acpi::fadt() {
+        //hardware-reduced ACPI has no PM1 control block, and port 0 is the
+        //DMA controller
+        if fadt.pm1a_control == 0 {
+            halt();
+        }
+
+        if let Some((slp_typa, slp_typb)) = acpi::sleep_type(b"_S5_") {
+            unsafe {
+                enable_acpi_mode(&fadt);
+
+                enter_sleep_state(fadt.pm1a_control as u16, slp_typa);
+                if fadt.pm1b_control != 0 {
+                    enter_sleep_state(fadt.pm1b_control as u16, slp_typb);
+                }
+            }
+        }
+    }
+
+    //nothing worked, leave the machine halted
+    halt();
+}
+
+//reset the machine, trying the ACPI reset register, then the keyboard
+//controller, then a triple fault
+pub fn reboot() -> ! {
+    int::disable();
+
+    let fadt = acpi::fadt();
+
+    if let Some(ref fadt) = fadt {
+        unsafe { acpi_reset(fadt); }
+    }
+
+    if fadt.as_ref().map_or(true, |fadt| fadt.has_8042()) {
+        unsafe { keyboard_controller_reset(); }
+    }
+
+    unsafe { triple_fault(); }
+}
+
+//stop the CPU for good
+pub fn halt() -> ! {
+    loop {
+        int::disable();
+        unsafe { asm!("hlt" :::: "volatile"); }
+    }
+}
+
+//switch from legacy to ACPI mode, if the firmware has not done so already
+unsafe fn enable_acpi_mode(fadt: &Fadt) {
+    if port_io::inw(fadt.pm1a_control as u16) & PM1_SCI_EN != 0 {
+        return;
+    }
+    if fadt.smi_command == 0 || fadt.acpi_enable == 0 {
+        return;
+    }
+
+    port_io::outb(fadt.smi_command as u16, fadt.acpi_enable);
+
+    //the switch can take a while, give up after a bounded wait
+    for _ in 0..1000 {
+        if port_io::inw(fadt.pm1a_control as u16) & PM1_SCI_EN != 0 {
+            break;
+        }
+        port_io::wait_for(1000);
+    }
+}
+
+//set SLP_TYP and SLP_EN in a PM1 control register, keeping SCI_EN and the
+//other bits the firmware set
+unsafe fn enter_sleep_state(port: u16, slp_typ: u8) {
+    let value = port_io::inw(port) & !PM1_SLP_TYP_MASK;
+    port_io::outw(port, value | ((slp_typ as u16) << PM1_SLP_TYP_SHIFT & PM1_SLP_TYP_MASK) | PM1_SLP_EN);
+}
+
+unsafe fn acpi_reset(fadt: &Fadt) {
+    let register = match fadt.reset_register {
+        Some(register) => register,
+        None => return,
+    };
+
+    if register.is_io() {
+        port_io::outb(register.address as u16, fadt.reset_value);
+    } else if register.is_memory() {
+        let address = register.address as usize;
+        mem::identity_map_range(address, 1,
+            EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::NO_EXECUTE);
+        ptr::write_volatile(address as *mut u8, fadt.reset_value);
+    }
+
+    //give the reset a moment to take effect
+    port_io::wait_for(1000);
+}
+
+unsafe fn keyboard_controller_reset() {
+    for _ in 0..0x10000 {
+        if port_io::inb(KBC_STATUS) & KBC_INPUT_FULL == 0 {
+            break;
+        }
+        port_io::wait();
+    }
+
+    port_io::outb(KBC_COMMAND, KBC_PULSE_RESET);
+    port_io::wait_for(1000);
+}
+
+//with an empty IDT, any interrupt escalates to a triple fault and a reset
+unsafe fn triple_fault() -> ! {
+    let ptr = IdtPointer::new();
+    asm!("lidt ($0); int3" :: "r" (&ptr) : "memory" : "volatile");
+
+    halt();
+}
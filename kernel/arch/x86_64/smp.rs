@@ -0,0 +1,190 @@
+// smp.rs
+// Bringing up the application processors listed in the MADT, through the
+// real-mode trampoline in boot/trampoline.asm and the INIT-SIPI-SIPI sequence
+
+use core::ptr;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use alloc::boxed::Box;
+use driver::vga;
+use driver::vga::Writer;
+use time;
+use time::{Duration, Instant};
+use arch::dev::apic;
+use arch::x86_64::{control, gdt, tss, percpu};
+use arch::x86_64::{new_tss, new_gdt, idt_load};
+use arch::x86_64::int::syscall;
+use arch::x86_64::mem;
+use arch::x86_64::mem::PhysicalAddress;
+use arch::x86_64::mem::entry::EntryFlags;
+
+// Where the trampoline is copied to, must match TRAMPOLINE_BASE in trampoline.asm.
+// The frame allocator never hands out memory below 1 MiB, so this page is free.
+const TRAMPOLINE: PhysicalAddress = 0x8000;
+
+// Stack size of each AP, in pages
+const AP_STACK_PAGES: usize = 16;
+
+// How long to wait for an AP to check in after each startup IPI
+const STARTUP_TIMEOUT_MS: u64 = 100;
+
+extern "C" {
+    static trampoline_start: u8;
+    static trampoline_end: u8;
+    static trampoline_cr3: u8;
+    static trampoline_stack: u8;
+    static trampoline_entry: u8;
+    static trampoline_cpu: u8;
+}
+
+// Set by an AP once it runs on its own GDT, IDT and local APIC
+static AP_READY: AtomicBool = AtomicBool::new(false);
+
+// The BSP counts as online from the start
+static CPUS_ONLINE: AtomicUsize = AtomicUsize::new(1);
+
+pub fn cpu_count() -> usize {
+    CPUS_ONLINE.load(Ordering::SeqCst)
+}
+
+// Address of a trampoline symbol in the copy below 1 MiB
+fn trampoline_address(symbol: &'static u8) -> usize {
+    let start = unsafe { &trampoline_start as *const u8 as usize };
+    TRAMPOLINE + (symbol as *const u8 as usize - start)
+}
+
+// Copy the trampoline into low memory, where a starting AP can reach it
+fn install_trampoline() {
+    let (start, end) = unsafe {
+        (&trampoline_start as *const u8 as usize, &trampoline_end as *const u8 as usize)
+    };
+
+    // The AP turns on paging with the kernel's tables while still running
+    // from here, so the page must stay identity mapped and executable
+    mem::identity_map_range(TRAMPOLINE, end - start, EntryFlags::PRESENT | EntryFlags::WRITABLE);
+
+    unsafe {
+        ptr::copy_nonoverlapping(start as *const u8, TRAMPOLINE as *mut u8, end - start);
+    }
+}
+
+// Start every enabled processor other than this one, returning the number
+// of CPUs that are online afterwards
+pub fn init() -> usize {
+    let (local, controller) = match (apic::local_apic(), apic::controller()) {
+        (Some(local), Some(controller)) => (local, controller),
+        _ => {
+            vga::info();
+            vga::println("No APIC, running on the bootstrap processor only");
+            return cpu_count();
+        }
+    };
+
+    // The trampoline loads CR3 while still in 32-bit mode
+    let cr3 = control::read_cr3();
+    if cr3 >> 32 != 0 {
+        vga::error();
+        write!(Writer::new(), "P4 table at {:#X} is above 4 GiB, APs cannot be started\n", cr3)
+            .expect("Unexpected failure in write!()");
+        return cpu_count();
+    }
+
+    install_trampoline();
+
+    let bsp_id = local.id();
+    for processor in controller.madt().processors.iter() {
+        if !processor.enabled || processor.apic_id == bsp_id {
+            continue;
+        }
+
+        let cpu = cpu_count();
+        if start_ap(processor.apic_id, cpu, cr3) {
+            CPUS_ONLINE.fetch_add(1, Ordering::SeqCst);
+        } else {
+            vga::error();
+            write!(Writer::new(), "Processor with APIC id {} did not start\n", processor.apic_id)
+                .expect("Unexpected failure in write!()");
+        }
+    }
+
+    vga::okay();
+    write!(Writer::new(), "{} CPU(s) online\n", cpu_count())
+        .expect("Unexpected failure in write!()");
+    cpu_count()
+}
+
+fn wait_ready(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if AP_READY.load(Ordering::SeqCst) {
+            return true;
+        }
+        unsafe { asm!("pause" :::: "volatile"); }
+    }
+
+    AP_READY.load(Ordering::SeqCst)
+}
+
+// cr3 must be below 4 GiB, the trampoline only loads the low half
+fn start_ap(apic_id: u32, cpu: usize, cr3: u64) -> bool {
+    let local = apic::local_apic().unwrap();
+
+    // The stack is never freed once the AP is up, it runs on it for good
+    let stack = match mem::alloc_stack("AP", AP_STACK_PAGES) {
+        Some(stack) => stack,
+        None => return false,
+    };
+
+    AP_READY.store(false, Ordering::SeqCst);
+    unsafe {
+        ptr::write_volatile(trampoline_address(&trampoline_cr3) as *mut u64, cr3);
+        ptr::write_volatile(trampoline_address(&trampoline_stack) as *mut u64, stack.top() as u64);
+        ptr::write_volatile(trampoline_address(&trampoline_entry) as *mut u64, ap_entry as u64);
+        ptr::write_volatile(trampoline_address(&trampoline_cpu) as *mut u64, cpu as u64);
+    }
+
+    let page = (TRAMPOLINE >> 12) as u8;
+
+    local.send_init(apic_id);
+    time::delay(Duration::from_millis(10));
+
+    // A second startup IPI is only needed if the first one was missed
+    local.send_startup(apic_id, page);
+    if wait_ready(Duration::from_millis(STARTUP_TIMEOUT_MS)) {
+        return true;
+    }
+
+    local.send_startup(apic_id, page);
+    if wait_ready(Duration::from_millis(STARTUP_TIMEOUT_MS)) {
+        return true;
+    }
+
+    // Put the AP back into wait-for-SIPI before the trampoline is filled in
+    // for the next one, so it cannot come up late on that one's stack and
+    // cpu id. Parked, it no longer needs its own stack
+    local.send_init(apic_id);
+    time::delay(Duration::from_millis(10));
+    mem::free_stack(stack);
+
+    false
+}
+
+// First Rust code run by an AP, on the stack set up by start_ap
+extern "C" fn ap_entry(cpu: usize) -> ! {
+    // Each CPU needs its own TSS, and so its own GDT to hold the descriptor
+    let tss: *mut tss::TaskStateSegment = Box::into_raw(Box::new(new_tss()));
+    let gdt: &'static gdt::Gdt = Box::leak(Box::new(new_gdt(tss)));
+    gdt.load();
+    tss::load(gdt::TSS_SELECTOR);
+    percpu::init(cpu, tss);
+
+    idt_load();
+    syscall::init();
+
+    let local = apic::local_apic().unwrap();
+    local.enable(apic::controller().unwrap().madt());
+
+    AP_READY.store(true, Ordering::SeqCst);
+
+    ::ap_main(cpu)
+}
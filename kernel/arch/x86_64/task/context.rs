@@ -0,0 +1,53 @@
+// context.rs
+// Saved CPU state of a thread that is not running, and the switch between threads
+
+use core::mem::size_of;
+
+extern "C" {
+    fn switch_context(old_rsp: *mut usize, new_rsp: usize);
+    fn thread_trampoline();
+}
+
+// RFLAGS of a new thread: interrupts stay off until it has finished the switch
+const INITIAL_RFLAGS: usize = 0x002;
+
+// Registers popped by switch_context, lowest address first
+#[repr(C)]
+struct SwitchFrame {
+    r15: usize,
+    r14: usize,
+    r13: usize,
+    r12: usize,
+    rbp: usize,
+    rbx: usize,
+    rflags: usize,
+    return_address: usize,
+}
+
+// Save the running thread's stack pointer to old_rsp and resume the thread
+// whose stack pointer is new_rsp. Returns once another thread switches back.
+// Interrupts must be disabled.
+pub unsafe fn switch(old_rsp: *mut usize, new_rsp: usize) {
+    switch_context(old_rsp, new_rsp);
+}
+
+// Build the first switch frame on an empty stack, so that switching to it
+// calls thread_start(arg). Returns the initial stack pointer.
+pub unsafe fn init_stack(top: usize, arg: usize) -> usize {
+    // thread_trampoline is entered with the stack 16 byte aligned, as the
+    // ABI expects right before its call
+    let frame = ((top & !0xF) - size_of::<SwitchFrame>()) as *mut SwitchFrame;
+
+    *frame = SwitchFrame {
+        r15: 0,
+        r14: 0,
+        r13: 0,
+        r12: arg,
+        rbp: 0,
+        rbx: 0,
+        rflags: INITIAL_RFLAGS,
+        return_address: thread_trampoline as usize,
+    };
+
+    frame as usize
+}
@@ -0,0 +1,5 @@
+// task/mod.rs
+// architecture support for kernel threads
+
+pub mod context;
+pub mod usermode;
@@ -0,0 +1,47 @@
+;switch.asm
+
+;switches the CPU between kernel threads
+;only the registers the System V ABI marks as callee-saved, and RFLAGS, need
+;saving, the caller of switch_context has already saved everything else
+
+global switch_context
+global thread_trampoline
+
+section .text
+bits 64
+  ;void switch_context(usize *old_rsp, usize new_rsp)
+  ;saves the current thread's registers on its stack, stores the stack pointer
+  ;at old_rsp, then restores the registers saved on the stack at new_rsp
+  align 16
+  switch_context:
+    pushfq
+    push rbx
+    push rbp
+    push r12
+    push r13
+    push r14
+    push r15
+
+    mov [rdi], rsp
+    mov rsp, rsi
+
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbp
+    pop rbx
+    popfq
+    ret
+
+  ;first code run by a new thread, switch_context returns here
+  ;r12 holds the argument for thread_start, set up when the stack was built
+  align 16
+  thread_trampoline:
+    mov rdi, r12
+
+    extern thread_start
+    call thread_start
+
+    ;thread_start never returns
+    ud2
@@ -0,0 +1,100 @@
+;user_test.asm
+
+;a flat user program exercising the system calls, copied into a process by
+;task::process::spawn. It only uses rip-relative addressing, so it runs
+;wherever it is loaded, and it is kept in .rodata as it is never run in place
+
+global user_test_start
+global user_test_end
+
+;system call numbers, see syscall/mod.rs
+SYS_WRITE equ 0
+SYS_EXIT equ 2
+SYS_SLEEP equ 4
+SYS_GETPID equ 5
+SYS_MMAP equ 6
+SYS_MUNMAP equ 7
+
+EFAULT equ 14
+
+section .rodata
+bits 64
+  user_test_start:
+    ;write(1, hello, hello_len)
+    mov rax, SYS_WRITE
+    mov rdi, 1
+    lea rsi, [rel hello]
+    mov rdx, hello_len
+    syscall
+
+    ;getpid(), through the int 0x80 fallback
+    mov rax, SYS_GETPID
+    int 0x80
+    mov r12, rax
+
+    ;sleep(100)
+    mov rax, SYS_SLEEP
+    mov rdi, 100
+    syscall
+
+    ;mmap(0, 4096, 0), then fill the page and print it from there
+    mov rax, SYS_MMAP
+    xor rdi, rdi
+    mov rsi, 4096
+    xor rdx, rdx
+    syscall
+    test rax, rax
+    js .failed
+    mov r13, rax
+
+    mov rdi, r13
+    lea rsi, [rel mapped]
+    mov rcx, mapped_len
+    rep movsb
+
+    mov rax, SYS_WRITE
+    mov rdi, 1
+    mov rsi, r13
+    mov rdx, mapped_len
+    syscall
+
+    ;munmap(page, 4096)
+    mov rax, SYS_MUNMAP
+    mov rdi, r13
+    mov rsi, 4096
+    syscall
+    test rax, rax
+    jnz .failed
+
+    ;a kernel address must be refused, not written out
+    mov rax, SYS_WRITE
+    mov rdi, 1
+    mov rsi, 0x100000
+    mov rdx, 16
+    syscall
+    cmp rax, -EFAULT
+    jne .failed
+
+    ;exit(pid)
+    mov rax, SYS_EXIT
+    mov rdi, r12
+    syscall
+
+  .failed:
+    mov rax, SYS_WRITE
+    mov rdi, 2
+    lea rsi, [rel failed]
+    mov rdx, failed_len
+    syscall
+
+    mov rax, SYS_EXIT
+    mov rdi, -1
+    syscall
+
+  hello: db "Hello from user mode", 10
+  hello_len equ $ - hello
+  mapped: db "Wrote to a page from mmap", 10
+  mapped_len equ $ - mapped
+  failed: db "User system call test failed", 10
+  failed_len equ $ - failed
+  user_test_end:
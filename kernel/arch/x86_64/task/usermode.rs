@@ -0,0 +1,58 @@
+// usermode.rs
+// Dropping a thread to ring 3, and the kernel stack it comes back in on
+
+use arch::x86_64::gdt;
+use arch::x86_64::percpu;
+use arch::x86_64::tss;
+use arch::x86_64::mem::VirtualAddress;
+
+// RFLAGS in user mode: interrupts enabled, plus the always-set bit 1
+const USER_RFLAGS: u64 = 0x202;
+
+// Set the stack the CPU switches to when an interrupt, exception or system
+// call arrives from ring 3. Called on every switch, with the incoming
+// thread's kernel stack
+pub fn set_kernel_stack(stack_top: VirtualAddress) {
+    per_cpu!(kernel_stack = stack_top);
+    unsafe {
+        tss::set_privilege_stack(percpu::tss(), 0, stack_top as u64);
+    }
+}
+
+// Leave the kernel for good, continuing at entry in ring 3 on the given user
+// stack. Both must be mapped USER_ACCESSIBLE. The thread only comes back into
+// the kernel through interrupts, exceptions and system calls, which start
+// afresh at the top of its kernel stack
+pub unsafe fn enter_usermode(entry: VirtualAddress, stack: VirtualAddress) -> ! {
+    // no interrupts once the user gs base is swapped in, iretq turns them
+    // back on through USER_RFLAGS. General purpose registers are cleared so
+    // no kernel values leak to user mode
+    asm!("cli
+          swapgs
+          push $0
+          push $1
+          push $2
+          push $3
+          push $4
+          xor rax, rax
+          xor rbx, rbx
+          xor rcx, rcx
+          xor rdx, rdx
+          xor rsi, rsi
+          xor rdi, rdi
+          xor rbp, rbp
+          xor r8, r8
+          xor r9, r9
+          xor r10, r10
+          xor r11, r11
+          xor r12, r12
+          xor r13, r13
+          xor r14, r14
+          xor r15, r15
+          iretq"
+        :: "r"(gdt::USER_DATA_SELECTOR as u64), "r"(stack as u64), "r"(USER_RFLAGS),
+           "r"(gdt::USER_CODE_SELECTOR as u64), "r"(entry as u64)
+        : "memory" : "intel", "volatile");
+
+    unreachable!("returned from user mode");
+}
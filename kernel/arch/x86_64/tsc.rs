@@ -0,0 +1,94 @@
+// tsc.rs
+// Time Stamp Counter, a per-CPU cycle counter that is usable as a clock
+// when it runs at a constant rate
+
+use spin::Once;
+use arch::x86_64::cpuid;
+use time::NANOS_PER_SEC;
+use time::clocksource::ClockSource;
+
+// Length of the calibration run
+const CALIBRATION_NANOS: u64 = 50_000_000;
+
+pub fn rdtsc() -> u64 {
+    let (low, high): (u32, u32);
+
+    unsafe {
+        asm!("rdtsc" : "={eax}"(low), "={edx}"(high) ::: "volatile");
+    }
+
+    (high as u64) << 32 | low as u64
+}
+
+pub struct Tsc {
+    frequency: u64,
+    // Nanoseconds per cycle as a 32.32 fixed point number
+    scale: u64,
+}
+
+impl Tsc {
+    pub fn frequency(&self) -> u64 {
+        self.frequency
+    }
+}
+
+impl ClockSource for Tsc {
+    fn name(&self) -> &'static str {
+        "TSC"
+    }
+
+    fn nanos(&self) -> u64 {
+        ((rdtsc() as u128 * self.scale as u128) >> 32) as u64
+    }
+
+    fn resolution(&self) -> u64 {
+        (NANOS_PER_SEC / self.frequency).max(1)
+    }
+}
+
+// Count TSC cycles over a fixed span of the reference clock. A tick based
+// reference only advances with interrupts enabled.
+pub fn calibrate(reference: &dyn ClockSource) -> u64 {
+    // Start right as the reference changes, so a coarse one loses no time
+    let first = reference.nanos();
+    let mut start = first;
+    while start == first {
+        start = reference.nanos();
+    }
+    let cycles_start = rdtsc();
+
+    let mut end = start;
+    while end - start < CALIBRATION_NANOS {
+        end = reference.nanos();
+    }
+    let cycles_end = rdtsc();
+
+    ((cycles_end - cycles_start) as u128 * NANOS_PER_SEC as u128 / (end - start) as u128) as u64
+}
+
+static TSC: Once<Tsc> = Once::new();
+
+// Use the TSC as a clock if it is invariant, calibrated against the reference
+pub fn init(reference: &dyn ClockSource) -> Option<&'static Tsc> {
+    if let Some(tsc) = TSC.try() {
+        return Some(tsc);
+    }
+
+    if !cpuid::has_tsc() || !cpuid::has_invariant_tsc() {
+        return None;
+    }
+
+    let frequency = calibrate(reference);
+    if frequency == 0 {
+        return None;
+    }
+
+    Some(TSC.call_once(|| Tsc {
+        frequency: frequency,
+        scale: (NANOS_PER_SEC << 32) / frequency,
+    }))
+}
+
+pub fn tsc() -> Option<&'static Tsc> {
+    TSC.try()
+}
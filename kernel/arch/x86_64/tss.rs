@@ -0,0 +1,63 @@
+//tss.rs
+//defines the Task State Segment, which in long mode only holds stack pointers
+//used when the CPU switches privilege level or takes an IST interrupt
+
+use core::ptr;
+use core::mem::size_of;
+
+//indexes into the interrupt stack table
+//(the IDT stores these plus one, since zero there means "no IST")
+pub const DOUBLE_FAULT_IST_INDEX: usize = 0;
+pub const NMI_IST_INDEX: usize = 1;
+pub const MACHINE_CHECK_IST_INDEX: usize = 2;
+
+//size of each IST stack, in pages
+pub const IST_STACK_PAGES: usize = 4;
+
+#[derive(Debug)]
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+    reserved_1: u32,
+    //stack pointers loaded when switching to rings 0-2
+    pub privilege_stack_table: [u64; 3],
+    reserved_2: u64,
+    //known-good stacks, selected per vector by the IDT
+    pub interrupt_stack_table: [u64; 7],
+    reserved_3: u64,
+    reserved_4: u16,
+    pub iomap_base: u16
+}
+
+impl TaskStateSegment {
+    pub const fn new() -> TaskStateSegment {
+        TaskStateSegment {
+            reserved_1: 0,
+            privilege_stack_table: [0; 3],
+            reserved_2: 0,
+            interrupt_stack_table: [0; 7],
+            reserved_3: 0,
+            reserved_4: 0,
+            //no I/O permission bitmap
+            iomap_base: size_of::<TaskStateSegment>() as u16
+        }
+    }
+}
+
+//set the stack loaded when an interrupt or exception moves the CPU from a
+//lower privilege level into the given ring. the TSS is packed, so the field
+//is written through a raw pointer, and it is shared with the CPU, which reads
+//it on every privilege change
+pub unsafe fn set_privilege_stack(tss: *mut TaskStateSegment, ring: usize, stack_top: u64) {
+    assert!(ring < 3, "no privilege stack for ring {}", ring);
+
+    //reserved_1 comes first
+    let field = (tss as *mut u8).add(size_of::<u32>() + ring * size_of::<u64>());
+    ptr::write_unaligned(field as *mut u64, stack_top);
+}
+
+//load the task register with the selector of a TSS descriptor in the GDT
+pub fn load(selector: u16) {
+    unsafe {
+        asm!("ltr $0" :: "r" (selector) : "memory");
+    }
+}
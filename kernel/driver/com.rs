@@ -2,10 +2,18 @@
 // serial port communication
 
 use arch::dev::port_io;
+use arch::dev::COM1_IRQ;
+use arch::x86_64::int::irq;
 use driver::vga;
+use driver::vga::Writer;
+use sync::SpinLock;
+use core::fmt::Write;
 
 const COM1: u16 = 0x3F8;
 
+// Held while sending, so strings from different callers are not interleaved
+static COM1_LOCK: SpinLock<()> = SpinLock::new(());
+
 pub fn init() {
     unsafe {
         // Disable all COM1 interrupts
@@ -28,10 +36,18 @@ pub fn init() {
         port_io::outb(COM1 + 4, 0x0B);
     }
 
+    irq::register_irq(COM1_IRQ, irq_handler).expect("Could not register COM1 handler");
+
     vga::okay();
     vga::println("COM1 serial port initialised");
 }
 
+// irq 4 handler
+fn irq_handler(_irq: u8) {
+    write!(Writer::new(), "COM1 INPUT RECEIVED: {}", read() as char)
+        .expect("Unexpected failure in write!()");
+}
+
 pub fn read() -> u8 {
     unsafe {
         while port_io::inb(COM1 + 5) & 0x01 == 0 {}
@@ -40,6 +56,11 @@ pub fn read() -> u8 {
 }
 
 pub fn write(byte: u8) {
+    let _guard = COM1_LOCK.lock();
+    send(byte);
+}
+
+fn send(byte: u8) {
     unsafe {
         while port_io::inb(COM1 + 5) &0x20 == 0 {}
         port_io::outb(COM1, byte);
@@ -51,8 +72,9 @@ pub fn write_char(c: char) {
 }
 
 pub fn write_str(str: &str) {
+    let _guard = COM1_LOCK.lock();
     for c in str.chars() {
-        write_char(c);
+        send(c as u8);
     }
 }
 
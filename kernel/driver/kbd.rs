@@ -2,8 +2,15 @@
 // contains methods that enable the user to perform keyboard input
 
 use arch::dev::port_io;
+use arch::dev::KBD_IRQ;
+use arch::x86_64::int::irq;
+use driver::vga;
+use alloc::collections::VecDeque;
+use sync::{SpinLock, WaitQueue};
 
 const PS2: u16 = 0x60;
+// Typed characters kept for read(), later ones are dropped once it is full
+const INPUT_BUFFER_SIZE: usize = 256;
 
 enum MOD {
 	SHIFT = 0,
@@ -11,40 +18,109 @@ enum MOD {
 	ALT = 2
 }
 
-static mut MODIFIERS: [bool; 3] = [false, false, false];
+// Updated from the keyboard irq, read by anyone decoding scancodes
+static MODIFIERS: SpinLock<[bool; 3]> = SpinLock::new([false, false, false]);
 
-// Scancode set 1 (my keyboard uses this)
-pub fn get_char() -> Option<char> {
-    let code = unsafe { port_io::inb(PS2) };
-    set_mods(code);
+// Filled by the keyboard irq, created on the first key press
+static INPUT: SpinLock<Option<VecDeque<u8>>> = SpinLock::new(None);
+// Threads blocked in read()
+static READERS: WaitQueue = WaitQueue::new();
+
+pub fn init() {
+    irq::register_irq(KBD_IRQ, irq_handler).expect("Could not register keyboard handler");
+
+    vga::okay();
+    vga::println("PS/2 keyboard initialised");
+}
+
+// irq 1 handler, echoes typed characters and buffers them for read()
+fn irq_handler(_irq: u8) {
+    match get_char() {
+        Some(res) => {
+            vga::print_char(res, 0x07);
+            push_input(res as u8);
+        },
+        // Do nothing if NONE returned
+        None => {}
+    }
+}
 
-    code_to_char(code)
+fn push_input(c: u8) {
+    {
+        let mut input = INPUT.lock();
+        let buffer = input.get_or_insert_with(VecDeque::new);
+        if buffer.len() >= INPUT_BUFFER_SIZE {
+            return;
+        }
+        buffer.push_back(c);
+    }
+
+    READERS.wake_all();
 }
 
-fn set_mods(code: u8) {
-    unsafe {
-        match code {
-            0x2A | 0x36 => MODIFIERS[0] = true,
-            0xAA | 0xB6 => MODIFIERS[0] = false,
-            0x3A => MODIFIERS[0] = !MODIFIERS[0],
-            
-            _ => {},
+// Block until at least one character has been typed, then take as many as
+// fit in buf. Returns the number of bytes read
+pub fn read(buf: &mut [u8]) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+
+    loop {
+        READERS.wait_until(|| INPUT.lock().as_ref().map_or(false, |input| !input.is_empty()));
+
+        // another reader may have emptied the buffer first
+        let count = take_input(buf);
+        if count > 0 {
+            return count;
         }
     }
 }
 
-fn code_to_char(code: u8) -> Option<char> {
-    let result;
+fn take_input(buf: &mut [u8]) -> usize {
+    let mut input = INPUT.lock();
+    let buffer = match input.as_mut() {
+        Some(buffer) => buffer,
+        None => return 0,
+    };
 
-    unsafe {
-        if MODIFIERS[0] {
-            result = get_shift(code);
-        } else {
-            result = get_reg(code);
+    let mut count = 0;
+    while count < buf.len() {
+        match buffer.pop_front() {
+            Some(c) => buf[count] = c,
+            None => break,
         }
+        count += 1;
+    }
+    count
+}
+
+// Scancode set 1 (my keyboard uses this)
+pub fn get_char() -> Option<char> {
+    let code = unsafe { port_io::inb(PS2) };
+    let mut modifiers = MODIFIERS.lock();
+    set_mods(&mut modifiers, code);
+
+    code_to_char(&modifiers, code)
+}
+
+fn set_mods(modifiers: &mut [bool; 3], code: u8) {
+    let shift = MOD::SHIFT as usize;
+
+    match code {
+        0x2A | 0x36 => modifiers[shift] = true,
+        0xAA | 0xB6 => modifiers[shift] = false,
+        0x3A => modifiers[shift] = !modifiers[shift],
+        
+        _ => {},
     }
+}
 
-    result
+fn code_to_char(modifiers: &[bool; 3], code: u8) -> Option<char> {
+    if modifiers[MOD::SHIFT as usize] {
+        get_shift(code)
+    } else {
+        get_reg(code)
+    }
 }
 
 fn get_shift(code: u8) -> Option<char> {
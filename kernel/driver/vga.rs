@@ -3,15 +3,26 @@
 //eg. print_char, print_line, terminal_clear etc
 
 use core::fmt;
+use sync::SpinLock;
 
-static mut VGA_COL: u32 = 0;
-static mut VGA_ROW: i32 = 0;
 const VGA_W: u32 = 80;
 const VGA_H: u32 = 25;
 const VGA_BUFF: usize = 0xB8000;
 
 extern crate rlibc;
 
+// Cursor position, a row of -1 means the screen is cleared before the next character
+struct Cursor {
+    col: u32,
+    row: i32
+}
+
+// Held while writing one string, byte slice or character, so output from
+// interrupt handlers and other CPUs is not mixed within it. write! takes it
+// once per formatted piece, so a write! can still be interleaved between
+// its pieces
+static CURSOR: SpinLock<Cursor> = SpinLock::new(Cursor { col: 0, row: 0 });
+
 pub fn print_char_at(c: u8, x: u32, y: u32, color: u8) {
     let offset : usize = ((y * VGA_W + x) * 2) as usize;
     let data : u16 = (color as u16) << 8 | (c as u16);
@@ -23,60 +34,86 @@ pub fn print_char(c: char, color: u8) {
 }
 
 pub fn print_byte(c: u8, color: u8) {
-    unsafe {
-    	if VGA_ROW <= -1 {
-            clear_term();
-	    VGA_ROW += 1;
-	}
-
-	match c {
-	    b'\n' => {
-	        VGA_COL = 0;
-		VGA_ROW += 1;
-	    },
-
-	    b'\t' => VGA_COL += 4,
-
-	    0x08 => {
-	        if VGA_COL == 0 {
-		    VGA_COL = 79;
-		    VGA_ROW -= 1;
-		}
-		else {
-		    VGA_COL -= 1
-		}
-
-	        ;print_char_at(b' ', VGA_COL, VGA_ROW as u32, color);
-	    },
-	    _ => {
-	        print_char_at(c, VGA_COL, VGA_ROW as u32, color);
-	        VGA_COL += 1;
-	    },
-    	};
-
-	if VGA_COL as u32 >= VGA_W {
-            VGA_ROW += 1;
-	    VGA_COL = 0;
-	}
-
-	if VGA_ROW as u32 >= VGA_H {
-	    VGA_ROW = -1;
-    	}
+    put_byte(&mut CURSOR.lock(), c, color);
+}
+
+fn put_byte(cursor: &mut Cursor, c: u8, color: u8) {
+    if cursor.row <= -1 {
+        clear_buffer();
+        cursor.row += 1;
+    }
+
+    match c {
+        b'\n' => {
+            cursor.col = 0;
+            cursor.row += 1;
+        },
+
+        b'\t' => cursor.col += 4,
+
+        0x08 => {
+            if cursor.col == 0 {
+                cursor.col = 79;
+                cursor.row -= 1;
+            }
+            else {
+                cursor.col -= 1
+            }
+
+            print_char_at(b' ', cursor.col, cursor.row as u32, color);
+        },
+        _ => {
+            print_char_at(c, cursor.col, cursor.row as u32, color);
+            cursor.col += 1;
+        },
+    };
+
+    if cursor.col >= VGA_W {
+        cursor.row += 1;
+        cursor.col = 0;
+    }
+
+    if cursor.row as u32 >= VGA_H {
+        cursor.row = -1;
     }
 }
 
 pub fn print(str: &str, color: u8) {
+    let mut cursor = CURSOR.lock();
     for c in str.chars() {
-        print_char(c, color);
+        put_byte(&mut cursor, c as u8, color);
+    }
+}
+
+// Raw bytes, such as a buffer written by a user process
+pub fn print_bytes(bytes: &[u8], color: u8) {
+    let mut cursor = CURSOR.lock();
+    for &c in bytes {
+        put_byte(&mut cursor, c, color);
     }
 }
 
 pub fn println(str: &str) {
-    print(str, 0x07);
-    print_char('\n', 0x07);
+    let mut cursor = CURSOR.lock();
+    for c in str.chars() {
+        put_byte(&mut cursor, c as u8, 0x07);
+    }
+    put_byte(&mut cursor, b'\n', 0x07);
 }
 
 pub fn clear_term() {
+    let mut cursor = CURSOR.lock();
+    clear_buffer();
+    cursor.col = 0;
+    cursor.row = 0;
+}
+
+// Release the screen if a panic struck while it was held
+pub unsafe fn force_unlock() {
+    CURSOR.force_unlock();
+}
+
+fn clear_buffer() {
     //loop through columns and rows, print whitespace char
     for x in 0..VGA_W as u32 {
         for y in 0..VGA_H as u32 {
@@ -89,6 +126,8 @@ pub fn clear_term() {
 
 // Writer structure, used for write! macro
 // enables the use of string formatting for debugging mem addresses, etc
+// the screen is locked for each piece of the output, not the whole write!,
+// so formatting an argument may itself print without deadlocking
 pub struct Writer {}
 
 impl Writer {
@@ -101,8 +140,9 @@ impl Writer {
 
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut cursor = CURSOR.lock();
         for byte in s.bytes() {
-            print_byte(byte, 0x07);
+            put_byte(&mut cursor, byte, 0x07);
         }
 
         Ok(())
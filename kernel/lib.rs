@@ -12,30 +12,62 @@
 #![feature(naked_functions)]
 #![feature(abi_x86_interrupt)]
 #![feature(linkage)]
+#![feature(alloc)]
+#![feature(alloc_error_handler)]
 
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
 extern crate bitflags;
 extern crate multiboot2;
+extern crate spin;
+extern crate alloc;
 
 mod driver;
+#[macro_use]
 mod arch;
 mod utils;
+mod time;
+mod task;
+mod sync;
+mod syscall;
 
 use core::intrinsics;
 use core::panic::PanicInfo;
+use core::alloc::Layout;
+use core::slice;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 use driver::vga;
 use driver::vga::Writer;
 use driver::com;
+use driver::kbd;
 use core::fmt::Write;
 use arch::dev::pic_init;
 use arch::dev::pit_init;
-use arch::dev::pit;
+use arch::dev::apic_init;
 use arch::x86_64::gdt_init;
 use arch::x86_64::idt_init;
+use arch::x86_64::acpi;
+use arch::x86_64::power;
+use arch::x86_64::smp;
+use arch::x86_64::percpu;
+use time::Duration;
 use arch::x86_64::int::int;
-use utils::qemu;
+use arch::x86_64::mem::area_frame_allocator::AreaFrameAllocator;
+use arch::x86_64::mem::frame::{FrameAllocator, Page};
+use arch::x86_64::mem::entry::EntryFlags;
+use arch::x86_64::mem;
+use arch::x86_64::mem::heap;
+
+// hlt, which faults outside ring 0
+static USER_FAULT_TEST: [u8; 1] = [0xF4];
+
+extern "C" {
+    // a user program making system calls, see arch/x86_64/task/user_test.asm
+    static user_test_start: u8;
+    static user_test_end: u8;
+}
 
 // called on system panic -- not implemented yet
 #[lang = "eh_personality"]
@@ -44,6 +76,8 @@ extern fn eh_personality() {}
 #[panic_handler]
 #[no_mangle]
 pub extern fn panic_fmt(_info: &PanicInfo) -> ! {
+    // the panic may have struck while the screen was held
+    unsafe { vga::force_unlock(); }
     vga::error();
     write!(Writer::new(), " System PANIC!\n")
         .expect("Unexpected error in writing panic information!()");
@@ -53,10 +87,18 @@ pub extern fn panic_fmt(_info: &PanicInfo) -> ! {
     loop{}
 }
 
+// called when the global allocator cannot satisfy a request
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    panic!("Heap allocation failed: {:?}", layout);
+}
+
 #[no_mangle]
 pub extern fn kernel_main(mb_info_ptr: usize) -> ! {
     let mb_info = unsafe { multiboot2::load(mb_info_ptr) };
 
+    let memory_map_tag = mb_info.get_memory_map()
+        .expect("Memory map tag required!");
     let elf_sections_tag = mb_info.get_elf_sections()
         .expect("ELF sections tag required!");
 
@@ -84,49 +126,147 @@ pub extern fn kernel_main(mb_info_ptr: usize) -> ! {
     write!(Writer::new(), "Kernel start: {:#X}, kernel end: {:#X}\n", 
 	kernel_start, kernel_end);
     vga::info();
-    write!(Writer::new(), "Multiboot start: {:#X}, Multiboot end: {:#X}\n\n", multiboot_start, multiboot_end);
+    write!(Writer::new(), "Multiboot start: {:#X}, Multiboot end: {:#X}\n", multiboot_start, multiboot_end);
+
+    let mut frame_allocator = AreaFrameAllocator::new(
+        kernel_start as usize, kernel_end as usize, multiboot_start, multiboot_end,
+        memory_map_tag.get_memory_areas());
+
+    let test_frame = frame_allocator.allocate_frame()
+        .expect("No physical frames available!");
+    vga::okay();
+    write!(Writer::new(), "Initialised frame allocator, first free frame at {:#X}\n",
+        test_frame.start());
+    frame_allocator.deallocate_frame(test_frame);
+
+    // Map, write to and unmap a scratch page to check the mapper works
+    let mut active_table = mem::remap_kernel(&mut frame_allocator, &mb_info,
+        multiboot_start, multiboot_end);
+    let test_page = Page::containing_address(0o_042_000_000_000_0000);
+    active_table.map(test_page, EntryFlags::WRITABLE, &mut frame_allocator)
+        .expect("Could not map the test page");
+    unsafe { *(test_page.start() as *mut u64) = 0xDEADBEEF; }
+    active_table.unmap(test_page, &mut frame_allocator);
+    vga::okay();
+    vga::println("Page mapper initialised");
+
+    heap::init(&mut active_table, &mut frame_allocator);
+
+    let heap_test = Box::new(42);
+    let mut vec_test: Vec<u32> = Vec::new();
+    for i in 0..100 {
+        vec_test.push(i);
+    }
+    vga::info();
+    write!(Writer::new(), "Heap test: boxed {} at {:p}, vec sum {}\n",
+        *heap_test, heap_test, vec_test.iter().sum::<u32>());
+
+    // Hand memory over to the controller, from here on slab caches can grow
+    mem::init_controller(active_table, frame_allocator);
+
+    let slab_test = Box::new([0u64; 4]);
+    let slab_stats = heap::slab_stats();
+    vga::info();
+    write!(Writer::new(), "Slab test: object at {:p}, {} cache holds {} page(s), {} in use\n\n",
+        slab_test, slab_stats[1].name, slab_stats[1].pages_held, slab_stats[1].objects_in_use);
 
     gdt_init();
+    percpu::init(0, arch::x86_64::bsp_tss());
+    sync::lockdep::init();
     idt_init();
+    arch::x86_64::int::syscall::init();
     pic_init();
     pit_init(1000);
+    kbd::init();
+    if acpi::init(mb_info_ptr) {
+        apic_init();
+    }
 
     int::enable();
     vga::okay();
     vga::println("Enabled interrupts\n");
 
+    time::clocksource::init();
+    smp::init();
+    match time::system::init() {
+        Some(now) => {
+            vga::okay();
+            write!(Writer::new(), "Read the RTC, it is {} UTC\n", now)
+                .expect("Unexpected failure in write!()");
+        }
+        None => {
+            vga::error();
+            vga::println("No CMOS RTC, wall-clock time is unknown");
+        }
+    }
+
+    task::init();
+    let thread_test = task::spawn("thread test", || {
+        vga::okay();
+        write!(Writer::new(), "Hello from kernel thread {}\n", task::current_id().unwrap())
+            .expect("Unexpected failure in write!()");
+    });
+    task::yield_now();
+    vga::info();
+    write!(Writer::new(), "Spawned and ran thread {}\n", thread_test)
+        .expect("Unexpected failure in write!()");
+    task::scheduler::print_threads();
+
+    // runs a privileged instruction in ring 3, which must only kill the process
+    let user_test = task::process::spawn("user test", &USER_FAULT_TEST)
+        .expect("Could not start user process");
+    vga::info();
+    write!(Writer::new(), "Started user process {}\n", user_test)
+        .expect("Unexpected failure in write!()");
+    task::yield_now();
+
+    let syscall_test = unsafe {
+        let start = &user_test_start as *const u8;
+        let length = &user_test_end as *const u8 as usize - start as usize;
+        task::process::spawn("syscall test", slice::from_raw_parts(start, length))
+    }.expect("Could not start user process");
+    vga::info();
+    write!(Writer::new(), "Started user process {}\n", syscall_test)
+        .expect("Unexpected failure in write!()");
+    task::sleep(Duration::from_millis(200));
+
     com::init();
     vga::info();
     vga::println("Sending test serial string...\n");
     com::write_str("\nHello from serial!\n");
 
     vga::info();
-    vga::print("Exiting QEMU in ", 0x07);
+    vga::print("Shutting down in ", 0x07);
     
     for i in (1..4).rev() {
         write!(Writer::new(), "{}...", i);
-        pit::timer_wait(1000);
+        task::sleep(Duration::from_secs(1));
     }
 
-    qemu::shutdown();
+    power::shutdown();
 
     //interrupt();
 
-    loop {}
-
-    // TODO
-    // ----
-    // - Add exception & hardware interrupt handlers to IDT
-    // - Allocate space for thread stacks
-    // - Halt the CPU until the next timer interrupt occurs, thereby enabling multi-threading
-
     // EXTRA
     // -----
-    // Create dynamic memory allocator
     // Create a mini kernel-space command-line
     // Begin writing filesystem implementation (filesystems, inodes, file descriptors, etc.)
 }
 
+// entered by each application processor once it is set up, see smp.rs
+#[no_mangle]
+pub extern fn ap_main(_cpu: usize) -> ! {
+    vga::okay();
+    write!(Writer::new(), "CPU {} online\n", per_cpu!(cpu_id))
+        .expect("Unexpected failure in write!()");
+
+    // nothing is scheduled on the APs yet
+    loop {
+        int::enable();
+        unsafe { asm!("hlt" :::: "volatile"); }
+    }
+}
+
 #[naked]
 #[inline(always)]
 pub fn interrupt() {
@@ -0,0 +1,51 @@
+// sync/condvar.rs
+// condition variable, used with a Mutex to wait until shared state changes
+
+use sync::WaitQueue;
+use sync::mutex::MutexGuard;
+use task;
+
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Condvar {
+        Condvar {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    // release the mutex and block until notified, then take it again.
+    // Wakeups can be spurious, so callers should check their condition in a loop
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = MutexGuard::mutex(&guard);
+
+        // queued before the mutex is released, so a notify in between is
+        // kept rather than lost
+        self.waiters.prepare_to_wait();
+        drop(guard);
+        task::block();
+        self.waiters.cancel_wait();
+
+        mutex.lock()
+    }
+
+    // block until the condition holds, with the mutex held when it is checked
+    pub fn wait_while<'a, T: ?Sized, F>(&self, mut guard: MutexGuard<'a, T>, mut condition: F)
+            -> MutexGuard<'a, T> where F: FnMut(&mut T) -> bool {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    // wake one waiting thread, safe to call from interrupt handlers
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+    }
+}
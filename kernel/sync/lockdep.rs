@@ -0,0 +1,233 @@
+// sync/lockdep.rs
+// lock order checker, only built into debug builds. Every spin lock taken
+// while another is held records an edge "held before taken"; taking two
+// locks in the opposite order to one seen before is a possible deadlock,
+// and is reported even if it never actually hangs
+
+//***************************************//
+// Locks are told apart by address, and  //
+// only the spin locks are tracked: they //
+// are held with interrupts off, so they //
+// never move between CPUs               //
+//***************************************//
+
+#[cfg(debug_assertions)]
+pub use self::checker::{init, acquire, acquire_shared, release, might_sleep, held_count};
+
+#[cfg(not(debug_assertions))]
+pub use self::disabled::{init, acquire, acquire_shared, release, might_sleep, held_count};
+
+#[cfg(debug_assertions)]
+mod checker {
+    use core::fmt::Write;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use spin::Mutex;
+    use arch::x86_64::percpu;
+    use driver::vga;
+    use driver::vga::Writer;
+
+    const MAX_CPUS: usize = 64;
+    // deepest nesting of spin locks tracked on one CPU
+    const MAX_HELD: usize = 16;
+    const MAX_EDGES: usize = 512;
+    // how far to follow edges looking for a cycle
+    const MAX_CHAIN: usize = 8;
+    // stop reporting after this many, the first ones are what matter
+    const MAX_REPORTS: usize = 16;
+
+    #[derive(Clone, Copy)]
+    struct HeldLocks {
+        locks: [usize; MAX_HELD],
+        depth: usize,
+        // set while checking, so locks taken to print a report are ignored
+        checking: bool,
+    }
+
+    // each CPU only touches its own entry, with interrupts disabled
+    static mut HELD: [HeldLocks; MAX_CPUS] = [HeldLocks {
+        locks: [0; MAX_HELD],
+        depth: 0,
+        checking: false,
+    }; MAX_CPUS];
+
+    struct OrderGraph {
+        // (held, taken) pairs
+        edges: [(usize, usize); MAX_EDGES],
+        count: usize,
+    }
+
+    impl OrderGraph {
+        fn contains(&self, from: usize, to: usize) -> bool {
+            self.edges[..self.count].iter().any(|&edge| edge == (from, to))
+        }
+
+        // whether to can be taken after from, directly or through other locks
+        fn reaches(&self, from: usize, to: usize, chain: usize) -> bool {
+            if chain == 0 {
+                return false;
+            }
+
+            self.edges[..self.count].iter()
+                .filter(|&&(held, _)| held == from)
+                .any(|&(_, taken)| taken == to || self.reaches(taken, to, chain - 1))
+        }
+
+        fn add(&mut self, from: usize, to: usize) {
+            if self.count < MAX_EDGES && !self.contains(from, to) {
+                self.edges[self.count] = (from, to);
+                self.count += 1;
+            }
+        }
+    }
+
+    // spin::Mutex rather than SpinLock, which would check itself
+    static ORDER: Mutex<OrderGraph> = Mutex::new(OrderGraph {
+        edges: [(0, 0); MAX_EDGES],
+        count: 0,
+    });
+
+    static ENABLED: AtomicBool = AtomicBool::new(false);
+    static REPORTS: AtomicUsize = AtomicUsize::new(0);
+
+    enum Problem {
+        Recursive(usize),
+        Inversion(usize, usize),
+        SleepWhileHeld(usize),
+    }
+
+    // start checking, once the per-CPU area of the bootstrap processor is
+    // set up. Every other CPU must set up its own before taking a spin lock
+    pub fn init() {
+        ENABLED.store(true, Ordering::SeqCst);
+    }
+
+    fn held_locks() -> Option<&'static mut HeldLocks> {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let cpu = percpu::cpu_id();
+        if cpu >= MAX_CPUS {
+            return None;
+        }
+
+        let held = unsafe { &mut HELD[cpu] };
+        if held.checking { None } else { Some(held) }
+    }
+
+    pub fn acquire(lock: usize) {
+        check_acquire(lock, false);
+    }
+
+    // readers of a RwLock may nest, so holding one already is not an error
+    pub fn acquire_shared(lock: usize) {
+        check_acquire(lock, true);
+    }
+
+    fn check_acquire(lock: usize, shared: bool) {
+        let held = match held_locks() {
+            Some(held) => held,
+            None => return,
+        };
+        held.checking = true;
+
+        let mut problem = None;
+        {
+            let mut order = ORDER.lock();
+            for &other in held.locks[..held.depth].iter() {
+                if other == lock {
+                    if !shared {
+                        problem = Some(Problem::Recursive(lock));
+                    }
+                } else if order.reaches(lock, other, MAX_CHAIN) {
+                    problem = Some(Problem::Inversion(other, lock));
+                } else {
+                    order.add(other, lock);
+                }
+            }
+        }
+
+        if let Some(problem) = problem {
+            report(problem);
+        }
+
+        if held.depth < MAX_HELD {
+            held.locks[held.depth] = lock;
+            held.depth += 1;
+        }
+        held.checking = false;
+    }
+
+    // locks are usually but not always released in reverse order
+    pub fn release(lock: usize) {
+        let held = match held_locks() {
+            Some(held) => held,
+            None => return,
+        };
+
+        let position = held.locks[..held.depth].iter().rposition(|&other| other == lock);
+        if let Some(index) = position {
+            for i in index..held.depth - 1 {
+                held.locks[i] = held.locks[i + 1];
+            }
+            held.depth -= 1;
+        }
+    }
+
+    // called before anything that can block the current thread
+    pub fn might_sleep() {
+        let held = match held_locks() {
+            Some(held) => held,
+            None => return,
+        };
+
+        if held.depth > 0 {
+            held.checking = true;
+            report(Problem::SleepWhileHeld(held.locks[held.depth - 1]));
+            held.checking = false;
+        }
+    }
+
+    // spin locks held by the running CPU
+    pub fn held_count() -> usize {
+        held_locks().map_or(0, |held| held.depth)
+    }
+
+    fn report(problem: Problem) {
+        if REPORTS.fetch_add(1, Ordering::Relaxed) >= MAX_REPORTS {
+            return;
+        }
+
+        vga::error();
+        match problem {
+            Problem::Recursive(lock) =>
+                write!(Writer::new(), "lockdep: lock {:#X} taken again by the CPU holding it\n", lock),
+            Problem::Inversion(held, lock) =>
+                write!(Writer::new(), "lockdep: lock {:#X} taken while holding {:#X}, \
+                    the opposite order was seen before\n", lock, held),
+            Problem::SleepWhileHeld(lock) =>
+                write!(Writer::new(), "lockdep: sleeping while holding spin lock {:#X}\n", lock),
+        }.expect("Unexpected failure in write!()");
+    }
+}
+
+#[cfg(not(debug_assertions))]
+mod disabled {
+    pub fn init() {}
+
+    #[inline(always)]
+    pub fn acquire(_lock: usize) {}
+
+    #[inline(always)]
+    pub fn acquire_shared(_lock: usize) {}
+
+    #[inline(always)]
+    pub fn release(_lock: usize) {}
+
+    #[inline(always)]
+    pub fn might_sleep() {}
+
+    pub fn held_count() -> usize {
+        0
+    }
+}
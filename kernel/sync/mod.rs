@@ -0,0 +1,23 @@
+// sync/mod.rs
+// locks and other synchronisation primitives. The spin locks disable
+// interrupts while held, so they are safe to share with interrupt handlers;
+// the sleeping ones block the calling thread and are for thread context only
+
+pub mod spinlock;
+pub mod ticket;
+pub mod rwlock;
+pub mod once;
+pub mod wait_queue;
+pub mod mutex;
+pub mod semaphore;
+pub mod condvar;
+pub mod lockdep;
+
+pub use self::spinlock::{SpinLock, SpinLockGuard};
+pub use self::ticket::{TicketLock, TicketLockGuard};
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use self::once::{Once, Lazy};
+pub use self::wait_queue::WaitQueue;
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::semaphore::Semaphore;
+pub use self::condvar::Condvar;
@@ -0,0 +1,126 @@
+// sync/mutex.rs
+// sleeping mutex: a thread that finds it held blocks until it is released,
+// instead of spinning. Only for thread context, never interrupt handlers.
+// It can be held across a switch, so lockdep only checks that no spin lock
+// is held when it is taken
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use sync::WaitQueue;
+use sync::lockdep;
+use task;
+
+// owner when the mutex is free, or was taken before the scheduler ran
+const NO_OWNER: usize = !0;
+
+pub struct Mutex<T: ?Sized> {
+    locked: AtomicBool,
+    owner: AtomicUsize,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+
+pub struct MutexGuard<'a, T: ?Sized + 'a> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Mutex<T> {
+        Mutex {
+            locked: AtomicBool::new(false),
+            owner: AtomicUsize::new(NO_OWNER),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub fn lock(&self) -> MutexGuard<T> {
+        let current = current_owner();
+        assert!(current == NO_OWNER || self.owner.load(Ordering::Relaxed) != current,
+            "thread tried to lock a mutex it already holds");
+
+        // checked even when the mutex is free, since next time it may not be
+        lockdep::might_sleep();
+        self.waiters.wait_until(|| self.try_acquire());
+        self.owner.store(current, Ordering::Relaxed);
+
+        MutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        if self.try_acquire() {
+            self.owner.store(current_owner(), Ordering::Relaxed);
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    // thread holding the mutex, if any
+    pub fn owner(&self) -> Option<task::ThreadId> {
+        match self.owner.load(Ordering::Relaxed) {
+            NO_OWNER => None,
+            id => Some(task::ThreadId(id)),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    fn try_acquire(&self) -> bool {
+        self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+
+    // release and let the longest waiting thread try again
+    fn unlock(&self) {
+        self.owner.store(NO_OWNER, Ordering::Relaxed);
+        self.locked.store(false, Ordering::Release);
+        self.waiters.wake_one();
+    }
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    // the mutex a guard holds, so a Condvar can release and retake it
+    pub fn mutex(guard: &MutexGuard<'a, T>) -> &'a Mutex<T> {
+        guard.mutex
+    }
+}
+
+impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+fn current_owner() -> usize {
+    task::current_id().map_or(NO_OWNER, |id| id.0)
+}
@@ -0,0 +1,89 @@
+// sync/once.rs
+// one-time initialisation: Once runs a closure exactly once, Lazy does so on
+// first use for a static
+
+use core::cell::UnsafeCell;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering, spin_loop_hint};
+
+const INCOMPLETE: usize = 0;
+const RUNNING: usize = 1;
+const COMPLETE: usize = 2;
+
+pub struct Once<T> {
+    state: AtomicUsize,
+    data: UnsafeCell<Option<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+unsafe impl<T: Send> Send for Once<T> {}
+
+impl<T> Once<T> {
+    pub const fn new() -> Once<T> {
+        Once {
+            state: AtomicUsize::new(INCOMPLETE),
+            data: UnsafeCell::new(None),
+        }
+    }
+
+    // run f if no call has yet, otherwise wait for the value the first
+    // caller produces. Calling it again from inside f never returns
+    pub fn call_once<F>(&self, f: F) -> &T where F: FnOnce() -> T {
+        if self.state.compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire).is_ok() {
+            unsafe { *self.data.get() = Some(f()); }
+            self.state.store(COMPLETE, Ordering::Release);
+        } else {
+            while self.state.load(Ordering::Acquire) != COMPLETE {
+                spin_loop_hint();
+            }
+        }
+
+        self.get().unwrap()
+    }
+
+    // the value, if initialisation has finished
+    pub fn try(&self) -> Option<&T> {
+        self.get()
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+
+    fn get(&self) -> Option<&T> {
+        if self.is_completed() {
+            unsafe { (*self.data.get()).as_ref() }
+        } else {
+            None
+        }
+    }
+}
+
+// a static initialised by a function on first dereference
+pub struct Lazy<T> {
+    once: Once<T>,
+    init: fn() -> T,
+}
+
+unsafe impl<T: Send + Sync> Sync for Lazy<T> {}
+
+impl<T> Lazy<T> {
+    pub const fn new(init: fn() -> T) -> Lazy<T> {
+        Lazy {
+            once: Once::new(),
+            init: init,
+        }
+    }
+
+    pub fn force(&self) -> &T {
+        self.once.call_once(self.init)
+    }
+}
+
+impl<T> Deref for Lazy<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.force()
+    }
+}
@@ -0,0 +1,137 @@
+// sync/rwlock.rs
+// reader-writer spin lock, any number of readers or a single writer. Disables
+// interrupts while held, like SpinLock
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering, spin_loop_hint};
+use arch::x86_64::int::int;
+use sync::lockdep;
+
+// low bit: held for writing, the rest counts readers
+const WRITER: usize = 1;
+const READER: usize = 2;
+
+pub struct RwLock<T: ?Sized> {
+    state: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+    interrupts: bool,
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+    interrupts: bool,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> RwLock<T> {
+        RwLock {
+            state: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    // readers only wait for a writer that already holds the lock, so a
+    // steady stream of them can keep writers out
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        let interrupts = int::are_enabled();
+        int::disable();
+
+        lockdep::acquire_shared(self.id());
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & WRITER == 0 && self.state.compare_exchange_weak(state, state + READER,
+                    Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                break;
+            }
+            spin_loop_hint();
+        }
+
+        RwLockReadGuard { lock: self, interrupts: interrupts }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        let interrupts = int::are_enabled();
+        int::disable();
+
+        lockdep::acquire(self.id());
+        while self.state.compare_exchange_weak(0, WRITER, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            spin_loop_hint();
+        }
+
+        RwLockWriteGuard { lock: self, interrupts: interrupts }
+    }
+
+    pub fn reader_count(&self) -> usize {
+        self.state.load(Ordering::Relaxed) / READER
+    }
+
+    pub fn is_write_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & WRITER != 0
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    fn id(&self) -> usize {
+        self as *const RwLock<T> as *const u8 as usize
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        lockdep::release(self.lock.id());
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+
+        if self.interrupts {
+            int::enable();
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        lockdep::release(self.lock.id());
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+
+        if self.interrupts {
+            int::enable();
+        }
+    }
+}
@@ -0,0 +1,111 @@
+// sync/spinlock.rs
+// spin lock that disables interrupts while held, so it can be shared between
+// normal code and interrupt handlers on the same CPU
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering, spin_loop_hint};
+use arch::x86_64::int::int;
+use sync::lockdep;
+
+pub struct SpinLock<T: ?Sized> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for SpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for SpinLock<T> {}
+
+// restores the interrupt flag that was saved when the lock was taken
+pub struct SpinLockGuard<'a, T: ?Sized + 'a> {
+    lock: &'a SpinLock<T>,
+    interrupts: bool,
+}
+
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> SpinLock<T> {
+        SpinLock {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    // disable interrupts, then spin until the lock is free
+    pub fn lock(&self) -> SpinLockGuard<T> {
+        let interrupts = int::are_enabled();
+        int::disable();
+
+        lockdep::acquire(self.id());
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            while self.locked.load(Ordering::Relaxed) {
+                spin_loop_hint();
+            }
+        }
+
+        SpinLockGuard { lock: self, interrupts: interrupts }
+    }
+
+    pub fn try_lock(&self) -> Option<SpinLockGuard<T>> {
+        let interrupts = int::are_enabled();
+        int::disable();
+
+        if self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+            lockdep::acquire(self.id());
+            Some(SpinLockGuard { lock: self, interrupts: interrupts })
+        } else {
+            if interrupts {
+                int::enable();
+            }
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    // release the lock without a guard, eg. so a panic can still print
+    pub unsafe fn force_unlock(&self) {
+        lockdep::release(self.id());
+        self.locked.store(false, Ordering::Release);
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    fn id(&self) -> usize {
+        self as *const SpinLock<T> as *const u8 as usize
+    }
+}
+
+impl<'a, T: ?Sized> Deref for SpinLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for SpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for SpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        lockdep::release(self.lock.id());
+        self.lock.locked.store(false, Ordering::Release);
+
+        if self.interrupts {
+            int::enable();
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// sync/ticket.rs
+// ticket lock: CPUs get the lock in the order they asked for it, so none can
+// be starved by the others. Disables interrupts while held, like SpinLock
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering, spin_loop_hint};
+use arch::x86_64::int::int;
+use sync::lockdep;
+
+pub struct TicketLock<T: ?Sized> {
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for TicketLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for TicketLock<T> {}
+
+pub struct TicketLockGuard<'a, T: ?Sized + 'a> {
+    lock: &'a TicketLock<T>,
+    interrupts: bool,
+}
+
+impl<T> TicketLock<T> {
+    pub const fn new(data: T) -> TicketLock<T> {
+        TicketLock {
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> TicketLock<T> {
+    pub fn lock(&self) -> TicketLockGuard<T> {
+        let interrupts = int::are_enabled();
+        int::disable();
+
+        lockdep::acquire(self.id());
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            spin_loop_hint();
+        }
+
+        TicketLockGuard { lock: self, interrupts: interrupts }
+    }
+
+    // only takes a ticket if it would be served straight away
+    pub fn try_lock(&self) -> Option<TicketLockGuard<T>> {
+        let interrupts = int::are_enabled();
+        int::disable();
+
+        let ticket = self.now_serving.load(Ordering::Acquire);
+        if self.next_ticket.compare_exchange(ticket, ticket.wrapping_add(1),
+                Ordering::Acquire, Ordering::Relaxed).is_ok() {
+            lockdep::acquire(self.id());
+            Some(TicketLockGuard { lock: self, interrupts: interrupts })
+        } else {
+            if interrupts {
+                int::enable();
+            }
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.next_ticket.load(Ordering::Relaxed) != self.now_serving.load(Ordering::Relaxed)
+    }
+
+    // number of CPUs holding or waiting for the lock
+    pub fn queue_length(&self) -> usize {
+        self.next_ticket.load(Ordering::Relaxed).wrapping_sub(self.now_serving.load(Ordering::Relaxed))
+    }
+
+    fn id(&self) -> usize {
+        self as *const TicketLock<T> as *const u8 as usize
+    }
+}
+
+impl<'a, T: ?Sized> Deref for TicketLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for TicketLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for TicketLockGuard<'a, T> {
+    fn drop(&mut self) {
+        lockdep::release(self.lock.id());
+        self.lock.now_serving.fetch_add(1, Ordering::Release);
+
+        if self.interrupts {
+            int::enable();
+        }
+    }
+}
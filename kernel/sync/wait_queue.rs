@@ -0,0 +1,107 @@
+// sync/wait_queue.rs
+// threads blocked until some condition holds, the building block of the
+// sleeping locks
+
+//***************************************//
+// A waiter must add itself, check its   //
+// condition again, and only then block. //
+// A wake that comes in between is kept  //
+// by the scheduler, so none are lost    //
+//***************************************//
+
+use alloc::collections::VecDeque;
+use arch::x86_64::int::int;
+use sync::{SpinLock, lockdep};
+use task;
+use task::ThreadId;
+
+pub struct WaitQueue {
+    // created on first use, so the queue can be built in a const fn
+    waiters: SpinLock<Option<VecDeque<ThreadId>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> WaitQueue {
+        WaitQueue {
+            waiters: SpinLock::new(None),
+        }
+    }
+
+    // add the current thread, unless it is already waiting
+    pub fn prepare_to_wait(&self) {
+        let id = current();
+        let mut waiters = self.waiters.lock();
+        let queue = waiters.get_or_insert_with(VecDeque::new);
+
+        if !queue.contains(&id) {
+            queue.push_back(id);
+        }
+    }
+
+    // take the current thread back out, when its condition held after all
+    pub fn cancel_wait(&self) {
+        let id = current();
+        if let Some(queue) = self.waiters.lock().as_mut() {
+            queue.retain(|&waiter| waiter != id);
+        }
+    }
+
+    // block until some condition holds, checking it each time the thread
+    // is woken
+    pub fn wait_until<F>(&self, mut condition: F) where F: FnMut() -> bool {
+        loop {
+            if condition() {
+                return;
+            }
+
+            self.prepare_to_wait();
+            if condition() {
+                self.cancel_wait();
+                return;
+            }
+
+            task::block();
+        }
+    }
+
+    // wake the longest waiting thread, returning false if there was none
+    pub fn wake_one(&self) -> bool {
+        let next = self.waiters.lock().as_mut().and_then(|queue| queue.pop_front());
+
+        match next {
+            Some(id) => {
+                task::wake(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // wake every waiting thread, returning how many there were
+    pub fn wake_all(&self) -> usize {
+        let woken = self.waiters.lock().take();
+
+        match woken {
+            Some(queue) => {
+                let count = queue.len();
+                for id in queue {
+                    task::wake(id);
+                }
+                count
+            }
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().as_ref().map_or(true, |queue| queue.is_empty())
+    }
+}
+
+// sleeping is only possible for a thread, and never from an interrupt
+// handler, with preemption disabled or while holding a spin lock
+fn current() -> ThreadId {
+    assert!(task::preemptible() && int::are_enabled(), "cannot sleep in atomic context");
+    lockdep::might_sleep();
+    task::current_id().expect("cannot sleep before the scheduler is running")
+}
@@ -0,0 +1,102 @@
+// syscall/calls.rs
+// the system calls themselves, in the order of their numbers
+
+use core::cmp;
+use arch::x86_64::mem::entry::EntryFlags;
+use arch::x86_64::mem::frame::PAGE_SIZE;
+use driver::{kbd, vga};
+use syscall::{user, Args, Error, PROT_EXEC};
+use task;
+use task::process;
+use time::{Duration, Instant};
+
+const STDIN: u64 = 0;
+const STDOUT: u64 = 1;
+const STDERR: u64 = 2;
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+
+// most bytes printed by one write. The screen is locked with interrupts off
+// while they are printed, longer writes are cut short and the process
+// writes the rest with another call
+const MAX_WRITE: u64 = 4096;
+
+// write(fd, buf, len) -> bytes written
+pub fn write(args: &Args) -> Result<u64, Error> {
+    let color = match args[0] {
+        STDOUT => 0x07,
+        STDERR => 0x04,
+        _ => return Err(Error::BadFile),
+    };
+
+    let buffer = user::slice(args[1], cmp::min(args[2], MAX_WRITE))?;
+    vga::print_bytes(buffer, color);
+    Ok(buffer.len() as u64)
+}
+
+// read(fd, buf, len) -> bytes read, blocking until a key is pressed
+pub fn read(args: &Args) -> Result<u64, Error> {
+    if args[0] != STDIN {
+        return Err(Error::BadFile);
+    }
+
+    let buffer = user::slice_mut(args[1], args[2])?;
+    Ok(kbd::read(buffer) as u64)
+}
+
+// exit(code), does not return
+pub fn exit(args: &Args) -> Result<u64, Error> {
+    process::exit(args[0] as i64);
+}
+
+// yield()
+pub fn yield_now(_args: &Args) -> Result<u64, Error> {
+    task::yield_now();
+    Ok(0)
+}
+
+// sleep(milliseconds), refused if the wake up time would not fit the clock
+pub fn sleep(args: &Args) -> Result<u64, Error> {
+    let duration = args[0].checked_mul(NANOS_PER_MILLI)
+        .map(Duration::from_nanos)
+        .ok_or(Error::Invalid)?;
+    Instant::now().checked_add(duration).ok_or(Error::Invalid)?;
+
+    task::sleep(duration);
+    Ok(0)
+}
+
+// getpid() -> pid
+pub fn getpid(_args: &Args) -> Result<u64, Error> {
+    process::current().map(|pid| pid.0 as u64).ok_or(Error::Invalid)
+}
+
+// mmap(addr, len, prot) -> address of zeroed memory. addr is a hint of
+// where to map it, or zero to let the kernel choose
+pub fn mmap(args: &Args) -> Result<u64, Error> {
+    let (address, length, prot) = (args[0], args[1], args[2]);
+    if length == 0 || length > process::USER_SIZE as u64 || address % PAGE_SIZE as u64 != 0 {
+        return Err(Error::Invalid);
+    }
+    if address != 0 && !user::in_user_space(address, length) {
+        return Err(Error::Invalid);
+    }
+
+    let mut flags = EntryFlags::WRITABLE;
+    if prot & PROT_EXEC == 0 {
+        flags |= EntryFlags::NO_EXECUTE;
+    }
+    process::map_memory(address as usize, length as usize, flags)
+        .map(|start| start as u64)
+        .ok_or(Error::NoMemory)
+}
+
+// munmap(addr, len), of memory returned by one mmap
+pub fn munmap(args: &Args) -> Result<u64, Error> {
+    let (address, length) = (args[0], args[1]);
+    if length == 0 || address % PAGE_SIZE as u64 != 0 || !user::in_user_space(address, length) {
+        return Err(Error::Invalid);
+    }
+
+    if process::unmap_memory(address as usize, length as usize) { Ok(0) } else { Err(Error::Invalid) }
+}
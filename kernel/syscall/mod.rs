@@ -0,0 +1,64 @@
+// syscall/mod.rs
+// system calls made by user processes. Both the SYSCALL entry and int 0x80
+// end up in dispatch(), with the number in rax and up to six arguments in
+// rdi, rsi, rdx, r10, r8 and r9. The result is returned in rax, a negative
+// value being an error number
+
+//***************************************//
+// Anything a process passes in is       //
+// untrusted: numbers are range checked, //
+// and buffers must lie in memory the    //
+// calling process has mapped            //
+//***************************************//
+
+pub mod user;
+mod calls;
+
+pub const SYS_WRITE: u64 = 0;
+pub const SYS_READ: u64 = 1;
+pub const SYS_EXIT: u64 = 2;
+pub const SYS_YIELD: u64 = 3;
+pub const SYS_SLEEP: u64 = 4;
+pub const SYS_GETPID: u64 = 5;
+pub const SYS_MMAP: u64 = 6;
+pub const SYS_MUNMAP: u64 = 7;
+
+// mmap protection flags, memory is always readable and writable
+pub const PROT_EXEC: u64 = 1 << 2;
+
+pub type Args = [u64; 6];
+type Handler = fn(&Args) -> Result<u64, Error>;
+
+// indexed by system call number
+const TABLE: [Option<Handler>; 8] = [
+    Some(calls::write),
+    Some(calls::read),
+    Some(calls::exit),
+    Some(calls::yield_now),
+    Some(calls::sleep),
+    Some(calls::getpid),
+    Some(calls::mmap),
+    Some(calls::munmap),
+];
+
+// returned to the process negated, numbered as on Linux
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    BadFile = 9,
+    NoMemory = 12,
+    BadAddress = 14,
+    Invalid = 22,
+    NoSyscall = 38,
+}
+
+pub fn dispatch(number: u64, args: Args) -> i64 {
+    let handler = match TABLE.get(number as usize) {
+        Some(&Some(handler)) => handler,
+        _ => return -(Error::NoSyscall as i64),
+    };
+
+    match handler(&args) {
+        Ok(value) => value as i64,
+        Err(error) => -(error as i64),
+    }
+}
@@ -0,0 +1,54 @@
+// syscall/user.rs
+// checks on pointers passed in by user processes, before the kernel
+// touches the memory behind them
+
+use core::slice;
+use syscall::Error;
+use task::process;
+
+// whether a range lies wholly in the user part of the address space
+pub fn in_user_space(address: u64, length: u64) -> bool {
+    let start = process::USER_START as u64;
+    let end = process::USER_END as u64;
+
+    match address.checked_add(length) {
+        Some(last) => address >= start && last <= end,
+        None => false,
+    }
+}
+
+// whether a range lies in a single region mapped by the running process,
+// and writable if it is to be written, so the kernel's access cannot fault
+fn check(address: u64, length: u64, write: bool) -> Result<(), Error> {
+    if !in_user_space(address, length) {
+        return Err(Error::BadAddress);
+    }
+
+    let start = address as usize;
+    let end = start + length as usize;
+    let mapped = process::with_current(|process| {
+        process.regions().iter().any(|region| {
+            start >= region.start && end <= region.end() && (region.writable() || !write)
+        })
+    });
+
+    if mapped == Some(true) { Ok(()) } else { Err(Error::BadAddress) }
+}
+
+// a buffer the process passed in to be read
+pub fn slice<'a>(address: u64, length: u64) -> Result<&'a [u8], Error> {
+    if length == 0 {
+        return Ok(&[]);
+    }
+    check(address, length, false)?;
+    Ok(unsafe { slice::from_raw_parts(address as *const u8, length as usize) })
+}
+
+// a buffer the process passed in to be filled
+pub fn slice_mut<'a>(address: u64, length: u64) -> Result<&'a mut [u8], Error> {
+    if length == 0 {
+        return Ok(&mut []);
+    }
+    check(address, length, true)?;
+    Ok(unsafe { slice::from_raw_parts_mut(address as *mut u8, length as usize) })
+}
@@ -0,0 +1,14 @@
+// task/mod.rs
+// kernel threads and the scheduler that shares the CPU between them
+
+pub mod thread;
+pub mod scheduler;
+pub mod policy;
+pub mod process;
+
+pub use self::thread::{Thread, ThreadId, ThreadInfo, ThreadState, ThreadStats};
+pub use self::scheduler::{init, spawn, yield_now, sleep, exit, block, wake, current_id};
+pub use self::scheduler::{set_nice, set_policy, thread_info, threads};
+pub use self::scheduler::{preempt_disable, preempt_enable, preemptible};
+pub use self::scheduler::set_page_table;
+pub use self::policy::SchedulerPolicy;
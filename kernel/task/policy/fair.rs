@@ -0,0 +1,152 @@
+// task/policy/fair.rs
+// fair share scheduling: every thread accumulates virtual runtime, its CPU
+// time scaled by a weight from its nice value, and the thread with the least
+// virtual runtime runs next
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use task::policy::{SchedulerPolicy, NICE_MIN};
+use task::thread::ThreadId;
+
+// weight of nice 0, each nice step changes the CPU share by about 10%
+const NICE_0_WEIGHT: u64 = 1024;
+const NICE_WEIGHTS: [u64; 40] = [
+    /* -20 */ 88761, 71755, 56483, 46273, 36291,
+    /* -15 */ 29154, 23254, 18705, 14949, 11916,
+    /* -10 */ 9548, 7620, 6100, 4904, 3906,
+    /*  -5 */ 3121, 2501, 1991, 1586, 1277,
+    /*   0 */ 1024, 820, 655, 526, 423,
+    /*   5 */ 335, 272, 215, 172, 137,
+    /*  10 */ 110, 87, 70, 56, 45,
+    /*  15 */ 36, 29, 23, 18, 15,
+];
+
+// period in which every ready thread should get to run once
+const TARGET_LATENCY_NANOS: u64 = 20_000_000;
+// shortest time a thread runs before it can be preempted
+const MIN_GRANULARITY_NANOS: u64 = 1_000_000;
+
+struct Entity {
+    weight: u64,
+    vruntime: u64,
+}
+
+pub struct Fair {
+    entities: BTreeMap<ThreadId, Entity>,
+    // ready threads, ordered by virtual runtime
+    ready: BTreeSet<(u64, ThreadId)>,
+    // never decreases, new and woken threads start near it
+    min_vruntime: u64,
+}
+
+impl Fair {
+    pub fn new() -> Fair {
+        Fair {
+            entities: BTreeMap::new(),
+            ready: BTreeSet::new(),
+            min_vruntime: 0,
+        }
+    }
+
+    fn weight(nice: i8) -> u64 {
+        NICE_WEIGHTS[(nice - NICE_MIN) as usize]
+    }
+
+    // CPU time converted to virtual runtime for a weight
+    fn scale(nanos: u64, weight: u64) -> u64 {
+        nanos * NICE_0_WEIGHT / weight
+    }
+
+    fn update_min_vruntime(&mut self) {
+        if let Some(&(vruntime, _)) = self.ready.iter().next() {
+            if vruntime > self.min_vruntime {
+                self.min_vruntime = vruntime;
+            }
+        }
+    }
+}
+
+impl SchedulerPolicy for Fair {
+    fn name(&self) -> &'static str {
+        "fair"
+    }
+
+    fn add(&mut self, id: ThreadId, nice: i8) {
+        let vruntime = self.min_vruntime;
+        self.entities.insert(id, Entity {
+            weight: Fair::weight(nice),
+            vruntime: vruntime,
+        });
+    }
+
+    fn remove(&mut self, id: ThreadId) {
+        if let Some(entity) = self.entities.remove(&id) {
+            self.ready.remove(&(entity.vruntime, id));
+        }
+    }
+
+    fn set_nice(&mut self, id: ThreadId, nice: i8) {
+        if let Some(entity) = self.entities.get_mut(&id) {
+            entity.weight = Fair::weight(nice);
+        }
+    }
+
+    fn enqueue(&mut self, id: ThreadId, woken: bool) {
+        let min_vruntime = self.min_vruntime;
+        let entity = match self.entities.get_mut(&id) {
+            Some(entity) => entity,
+            None => return,
+        };
+
+        // a thread that slept gets a small head start, so interactive threads
+        // respond quickly, but it cannot bank the time it spent asleep
+        if woken {
+            let floor = min_vruntime.saturating_sub(TARGET_LATENCY_NANOS / 2);
+            if entity.vruntime < floor {
+                entity.vruntime = floor;
+            }
+        }
+
+        self.ready.insert((entity.vruntime, id));
+    }
+
+    fn pick_next(&mut self) -> Option<ThreadId> {
+        let next = *self.ready.iter().next()?;
+        self.ready.remove(&next);
+        self.update_min_vruntime();
+
+        Some(next.1)
+    }
+
+    fn has_ready(&self) -> bool {
+        !self.ready.is_empty()
+    }
+
+    fn charge(&mut self, id: ThreadId, nanos: u64) {
+        if let Some(entity) = self.entities.get_mut(&id) {
+            entity.vruntime += Fair::scale(nanos, entity.weight);
+        }
+    }
+
+    fn should_preempt(&mut self, current: ThreadId, nanos: u64) -> bool {
+        if nanos < MIN_GRANULARITY_NANOS {
+            return false;
+        }
+
+        let entity = match self.entities.get(&current) {
+            Some(entity) => entity,
+            None => return true,
+        };
+
+        // the running thread's virtual runtime, counting the time not yet charged
+        let vruntime = entity.vruntime + Fair::scale(nanos, entity.weight);
+
+        // give way once it is a slice ahead of the neediest ready thread
+        match self.ready.iter().next() {
+            Some(&(next, _)) => {
+                let slice = TARGET_LATENCY_NANOS / (self.ready.len() as u64 + 1);
+                vruntime > next + slice.max(MIN_GRANULARITY_NANOS)
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,41 @@
+// task/policy/mod.rs
+// the decisions of which ready thread runs next, and for how long
+
+pub mod round_robin;
+pub mod fair;
+
+pub use self::round_robin::RoundRobin;
+pub use self::fair::Fair;
+
+use task::thread::ThreadId;
+
+// highest and lowest priority nice values, 0 is the default
+pub const NICE_MIN: i8 = -20;
+pub const NICE_MAX: i8 = 19;
+
+pub trait SchedulerPolicy: Send {
+    fn name(&self) -> &'static str;
+
+    // a thread was created, or the policy took over from another one
+    fn add(&mut self, id: ThreadId, nice: i8);
+
+    // a thread exited
+    fn remove(&mut self, id: ThreadId);
+
+    fn set_nice(&mut self, id: ThreadId, nice: i8);
+
+    // a thread became ready to run. woken is true if it had been blocked
+    fn enqueue(&mut self, id: ThreadId, woken: bool);
+
+    // take the next thread to run off the ready set
+    fn pick_next(&mut self) -> Option<ThreadId>;
+
+    fn has_ready(&self) -> bool;
+
+    // a thread ran for the given time before being switched out
+    fn charge(&mut self, id: ThreadId, nanos: u64);
+
+    // checked every timer tick: should the running thread, which has run for
+    // the given time since it was switched in, give way to a ready one
+    fn should_preempt(&mut self, current: ThreadId, nanos: u64) -> bool;
+}
@@ -0,0 +1,53 @@
+// task/policy/round_robin.rs
+// every thread gets the same time slice in turn, nice values are ignored
+
+use alloc::collections::VecDeque;
+use task::policy::SchedulerPolicy;
+use task::thread::ThreadId;
+
+// time a thread may run before it is preempted
+pub const TIME_SLICE_NANOS: u64 = 10_000_000;
+
+pub struct RoundRobin {
+    run_queue: VecDeque<ThreadId>,
+}
+
+impl RoundRobin {
+    pub fn new() -> RoundRobin {
+        RoundRobin {
+            run_queue: VecDeque::new(),
+        }
+    }
+}
+
+impl SchedulerPolicy for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round-robin"
+    }
+
+    fn add(&mut self, _id: ThreadId, _nice: i8) {}
+
+    fn remove(&mut self, id: ThreadId) {
+        self.run_queue.retain(|&queued| queued != id);
+    }
+
+    fn set_nice(&mut self, _id: ThreadId, _nice: i8) {}
+
+    fn enqueue(&mut self, id: ThreadId, _woken: bool) {
+        self.run_queue.push_back(id);
+    }
+
+    fn pick_next(&mut self) -> Option<ThreadId> {
+        self.run_queue.pop_front()
+    }
+
+    fn has_ready(&self) -> bool {
+        !self.run_queue.is_empty()
+    }
+
+    fn charge(&mut self, _id: ThreadId, _nanos: u64) {}
+
+    fn should_preempt(&mut self, _current: ThreadId, nanos: u64) -> bool {
+        nanos >= TIME_SLICE_NANOS
+    }
+}
@@ -0,0 +1,343 @@
+// task/process.rs
+// user processes: a thread that runs in ring 3, and the user memory mapped
+// for it. Each process is a single thread, and its pid is that thread's id
+
+//***************************************//
+// Each process has its own page table.  //
+// The kernel part is shared by all of  //
+// them, user memory is mapped at the    //
+// same addresses in each but is only    //
+// visible in its own process's table    //
+//***************************************//
+
+use core::fmt::Write;
+use core::ptr;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use arch::x86_64::mem;
+use arch::x86_64::mem::{InactivePageTable, VirtualAddress};
+use arch::x86_64::mem::entry::EntryFlags;
+use arch::x86_64::mem::frame::{Page, PAGE_SIZE};
+use arch::x86_64::task::usermode;
+use driver::vga;
+use driver::vga::Writer;
+use sync::{Lazy, SpinLock};
+use task;
+use task::ThreadId;
+
+// user memory starts at the first P4 entry not used by the kernel, see
+// mem::KERNEL_P4_ENTRIES. Code is loaded at the bottom, the stack is at the top
+pub const USER_START: VirtualAddress = 0x0000_1000_0000_0000;
+pub const USER_END: VirtualAddress = USER_START + USER_SIZE;
+pub const USER_SIZE: usize = 1024 * 1024 * 1024; // 1 GiB
+
+pub const USER_STACK_PAGES: usize = 16;
+
+// most memory a process can have mapped at once, stack and code included,
+// so one process cannot take every free frame
+pub const MAX_MAPPED_PAGES: usize = 4096; // 16 MiB
+
+pub type ProcessId = ThreadId;
+
+// pages mapped for a process, freed with its page table when it ends
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    pub start: VirtualAddress,
+    pub pages: usize,
+    // what the process may do with the pages, besides read them
+    pub flags: EntryFlags,
+}
+
+impl Region {
+    pub fn end(&self) -> VirtualAddress {
+        self.start + self.pages * PAGE_SIZE as usize
+    }
+
+    pub fn overlaps(&self, start: VirtualAddress, end: VirtualAddress) -> bool {
+        start < self.end() && self.start < end
+    }
+
+    pub fn writable(&self) -> bool {
+        self.flags.contains(EntryFlags::WRITABLE)
+    }
+}
+
+pub struct Process {
+    pid: ProcessId,
+    name: &'static str,
+    page_table: InactivePageTable,
+    regions: Vec<Region>,
+}
+
+impl Process {
+    pub fn pid(&self) -> ProcessId {
+        self.pid
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub fn mapped_pages(&self) -> usize {
+        self.regions.iter().map(|region| region.pages).sum()
+    }
+
+    // whether pages can be mapped at start without leaving user memory or
+    // landing on memory the process already has
+    fn is_free(&self, start: VirtualAddress, pages: usize) -> bool {
+        let end = match pages.checked_mul(PAGE_SIZE as usize).and_then(|size| start.checked_add(size)) {
+            Some(end) => end,
+            None => return false,
+        };
+
+        start % PAGE_SIZE as usize == 0 && start >= USER_START && end <= USER_END &&
+            !self.regions.iter().any(|region| region.overlaps(start, end))
+    }
+
+    // lowest user address with room for the given number of pages
+    fn find_free(&self, pages: usize) -> Option<VirtualAddress> {
+        let mut taken: Vec<Region> = self.regions.clone();
+        taken.sort_by_key(|region| region.start);
+
+        let size = pages * PAGE_SIZE as usize;
+        let mut start = USER_START;
+        for region in taken.iter() {
+            if start + size <= region.start {
+                break;
+            }
+            if region.end() > start {
+                start = region.end();
+            }
+        }
+
+        if self.is_free(start, pages) { Some(start) } else { None }
+    }
+}
+
+static PROCESSES: Lazy<SpinLock<BTreeMap<ProcessId, Process>>> = Lazy::new(process_table);
+
+fn process_table() -> SpinLock<BTreeMap<ProcessId, Process>> {
+    SpinLock::new(BTreeMap::new())
+}
+
+pub fn pages_for(length: usize) -> usize {
+    (length + PAGE_SIZE as usize - 1) / PAGE_SIZE as usize
+}
+
+// map fresh frames for a region of user memory in the active page table,
+// zeroed apart from the data copied to their start. If the frames run out
+// the pages mapped so far are unmapped again and false is returned
+pub fn map_region(region: Region, data: &[u8]) -> bool {
+    let first = Page::containing_address(region.start);
+    let last = Page::containing_address(region.end() - 1);
+
+    // writable at first, whatever the region's flags, to be filled in
+    let mapped = mem::with_controller(|controller| {
+        let flags = region.flags | EntryFlags::USER_ACCESSIBLE | EntryFlags::WRITABLE;
+        for (count, page) in Page::range_inclusive(first, last).enumerate() {
+            if controller.map_page(page, flags).is_err() {
+                for page in Page::range_inclusive(first, last).take(count) {
+                    controller.unmap_page(page);
+                }
+                return false;
+            }
+        }
+        true
+    });
+    if !mapped {
+        return false;
+    }
+
+    // the frames may hold another process's data
+    unsafe {
+        ptr::write_bytes(region.start as *mut u8, 0, region.pages * PAGE_SIZE as usize);
+        ptr::copy_nonoverlapping(data.as_ptr(), region.start as *mut u8, data.len());
+    }
+
+    if !region.writable() {
+        mem::with_controller(|controller| {
+            for page in Page::range_inclusive(first, last) {
+                controller.set_page_flags(page, region.flags | EntryFlags::USER_ACCESSIBLE);
+            }
+        });
+    }
+
+    true
+}
+
+pub fn unmap_region(region: Region) {
+    let first = Page::containing_address(region.start);
+    let last = Page::containing_address(region.end() - 1);
+
+    mem::with_controller(|controller| {
+        for page in Page::range_inclusive(first, last) {
+            controller.unmap_page(page);
+        }
+    });
+}
+
+// map more memory for the running process, at address if it is not zero and
+// otherwise wherever it fits. Returns where the memory was mapped
+pub fn map_memory(address: VirtualAddress, length: usize, flags: EntryFlags) -> Option<VirtualAddress> {
+    let pages = pages_for(length);
+    if pages == 0 || pages > MAX_MAPPED_PAGES {
+        return None;
+    }
+
+    // the region is reserved under the lock, and mapped after it is dropped
+    let region = with_current(|process| {
+        if process.mapped_pages() + pages > MAX_MAPPED_PAGES {
+            return None;
+        }
+
+        let start = if address == 0 { process.find_free(pages)? } else { address };
+        if !process.is_free(start, pages) {
+            return None;
+        }
+
+        let region = Region { start: start, pages: pages, flags: flags };
+        process.regions.push(region);
+        Some(region)
+    })??;
+
+    if !map_region(region, &[]) {
+        with_current(|process| process.regions.retain(|other| other.start != region.start));
+        return None;
+    }
+    Some(region.start)
+}
+
+// unmap memory of the running process. Only whole regions, exactly as they
+// were mapped, can be unmapped
+pub fn unmap_memory(address: VirtualAddress, length: usize) -> bool {
+    let pages = pages_for(length);
+    let region = with_current(|process| {
+        let index = process.regions.iter()
+            .position(|region| region.start == address && region.pages == pages)?;
+        Some(process.regions.remove(index))
+    });
+
+    match region {
+        Some(Some(region)) => {
+            unmap_region(region);
+            true
+        }
+        _ => false,
+    }
+}
+
+// start a process running a flat binary image, which is loaded read-only at
+// the bottom of user memory and entered at its first byte
+pub fn spawn(name: &'static str, image: &[u8]) -> Option<ProcessId> {
+    let code_pages = pages_for(image.len());
+    if code_pages == 0 || code_pages + USER_STACK_PAGES > MAX_MAPPED_PAGES {
+        return None;
+    }
+
+    let code = Region {
+        start: USER_START,
+        pages: code_pages,
+        flags: EntryFlags::empty(),
+    };
+    let stack = Region {
+        start: USER_END - USER_STACK_PAGES * PAGE_SIZE as usize,
+        pages: USER_STACK_PAGES,
+        flags: EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE,
+    };
+
+    let page_table = mem::new_user_table();
+    let p4 = page_table.p4_address();
+
+    // the new table is only loaded while its memory is filled in, so this
+    // thread must not be switched out meanwhile. The process must also be in
+    // the table before its thread can first run
+    task::preempt_disable();
+    let previous = mem::active_page_table();
+    mem::load_page_table(p4);
+    let mapped = map_region(code, image) && map_region(stack, &[]);
+    mem::load_page_table(previous);
+
+    // whatever was mapped goes with the table
+    if !mapped {
+        task::preempt_enable();
+        mem::free_user_table(page_table);
+        return None;
+    }
+
+    let mut regions = Vec::new();
+    regions.push(code);
+    regions.push(stack);
+
+    let pid = task::spawn(name, move || {
+        unsafe { usermode::enter_usermode(USER_START, USER_END); }
+    });
+    task::set_page_table(pid, Some(p4));
+    PROCESSES.lock().insert(pid, Process {
+        pid: pid,
+        name: name,
+        page_table: page_table,
+        regions: regions,
+    });
+    task::preempt_enable();
+
+    Some(pid)
+}
+
+// the process the running thread belongs to, if any
+pub fn current() -> Option<ProcessId> {
+    let pid = task::current_id()?;
+    if PROCESSES.lock().contains_key(&pid) { Some(pid) } else { None }
+}
+
+// run a closure against the running process
+pub fn with_current<F, R>(f: F) -> Option<R> where F: FnOnce(&mut Process) -> R {
+    let pid = task::current_id()?;
+    PROCESSES.lock().get_mut(&pid).map(f)
+}
+
+// take the running process out of the table and free its memory, returning
+// its pid and name
+fn release_current() -> Option<(ProcessId, &'static str)> {
+    let process = {
+        let pid = task::current_id()?;
+        PROCESSES.lock().remove(&pid)?
+    };
+
+    // back to the kernel's page table, so the process's can be freed
+    task::set_page_table(process.pid, None);
+
+    let Process { pid, name, page_table, .. } = process;
+    mem::free_user_table(page_table);
+
+    Some((pid, name))
+}
+
+// end the running process
+pub fn exit(code: i64) -> ! {
+    if let Some((pid, name)) = release_current() {
+        vga::info();
+        write!(Writer::new(), "Process {} ({}) exited with code {}\n", pid, name, code)
+            .expect("Unexpected failure in write!()");
+    }
+
+    task::exit();
+}
+
+// end the running process after it faulted, instead of halting the kernel
+pub fn kill_current(reason: &str) -> ! {
+    if let Some((pid, name)) = release_current() {
+        vga::error();
+        write!(Writer::new(), "Process {} ({}) killed: {}\n", pid, name, reason)
+            .expect("Unexpected failure in write!()");
+    }
+
+    task::exit();
+}
+
+pub fn count() -> usize {
+    PROCESSES.lock().len()
+}
@@ -0,0 +1,534 @@
+// task/scheduler.rs
+// thread scheduler, preempted from the timer interrupt. Which thread runs
+// next is left to a pluggable SchedulerPolicy.
+
+//***************************************//
+// The scheduler lock is only ever taken //
+// with interrupts disabled, and never   //
+// held across a switch                  //
+//***************************************//
+
+use core::fmt::Write;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use spin::Mutex;
+use arch::x86_64::int::int;
+use arch::x86_64::percpu;
+use arch::x86_64::mem;
+use arch::x86_64::mem::PhysicalAddress;
+use arch::x86_64::mem::stack::Stack;
+use arch::x86_64::task::context;
+use arch::x86_64::task::usermode;
+use driver::vga;
+use driver::vga::Writer;
+use task::policy::{SchedulerPolicy, Fair, NICE_MIN, NICE_MAX};
+use task::thread::{Thread, ThreadEntry, ThreadId, ThreadInfo, ThreadState};
+use time;
+use time::{Duration, Instant};
+use time::timer;
+
+pub const THREAD_STACK_PAGES: usize = 4;
+
+const BOOT_THREAD: ThreadId = ThreadId(0);
+
+struct Scheduler {
+    threads: BTreeMap<ThreadId, Box<Thread>>,
+    policy: Box<dyn SchedulerPolicy>,
+    current: ThreadId,
+    idle: ThreadId,
+    next_id: usize,
+    // when the current thread was switched in, on the monotonic clock
+    switched_in: u64,
+    // exited threads whose stacks are still to be freed
+    dead: Vec<ThreadId>,
+}
+
+impl Scheduler {
+    fn thread(&mut self, id: ThreadId) -> &mut Thread {
+        self.threads.get_mut(&id).expect("no such thread")
+    }
+
+    fn allocate_id(&mut self) -> ThreadId {
+        let id = ThreadId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    // the idle thread only runs when nothing else can, so the policy never
+    // sees it
+    fn add(&mut self, mut thread: Box<Thread>) {
+        let id = thread.id();
+        thread.state = ThreadState::Ready;
+        thread.ready_since = time::now_nanos();
+        let nice = thread.nice;
+        self.threads.insert(id, thread);
+
+        if id != self.idle {
+            self.policy.add(id, nice);
+            self.policy.enqueue(id, false);
+        }
+    }
+
+    // account the time the current thread has run since it was switched in
+    fn charge_current(&mut self, now: u64) {
+        let current = self.current;
+        let ran = now.saturating_sub(self.switched_in);
+        self.switched_in = now;
+
+        self.thread(current).stats.runtime += ran;
+        if current != self.idle {
+            self.policy.charge(current, ran);
+        }
+    }
+
+    // pick the next thread to run, returning where to save the current stack
+    // pointer and the stack pointer to resume, or none to keep running
+    fn switch_next(&mut self, preempted: bool) -> Option<(*mut usize, usize)> {
+        let old = self.current;
+        let idle = self.idle;
+        let now = time::now_nanos();
+
+        self.charge_current(now);
+
+        if self.thread(old).state == ThreadState::Running {
+            if !self.policy.has_ready() {
+                return None;
+            }
+
+            {
+                let thread = self.thread(old);
+                thread.state = ThreadState::Ready;
+                thread.ready_since = now;
+            }
+            if old != idle {
+                self.policy.enqueue(old, false);
+            }
+        }
+
+        let next = self.policy.pick_next().unwrap_or(idle);
+        {
+            let thread = self.thread(next);
+            thread.state = ThreadState::Running;
+            if next != idle {
+                thread.stats.wait_time += now.saturating_sub(thread.ready_since);
+            }
+        }
+        self.current = next;
+        per_cpu!(current_thread = next.0);
+
+        if next == old {
+            return None;
+        }
+
+        {
+            let stats = &mut self.thread(old).stats;
+            stats.context_switches += 1;
+            if preempted {
+                stats.preemptions += 1;
+            }
+        }
+
+        // interrupts and system calls from ring 3 arrive on the stack of
+        // the thread that is running
+        if let Some(stack) = self.thread(next).stack() {
+            usermode::set_kernel_stack(stack.top());
+        }
+        mem::load_page_table(self.thread(next).page_table.unwrap_or_else(mem::kernel_page_table));
+
+        let old_rsp = &mut self.thread(old).rsp as *mut usize;
+        let new_rsp = self.thread(next).rsp;
+        Some((old_rsp, new_rsp))
+    }
+
+    // remove exited threads that are no longer running, returning their stacks
+    fn reap(&mut self) -> Vec<Stack> {
+        let current = self.current;
+        let mut stacks = Vec::new();
+
+        let dead: Vec<ThreadId> = self.dead.drain(..).collect();
+        for id in dead {
+            if id == current {
+                self.dead.push(id);
+                continue;
+            }
+
+            if let Some(mut thread) = self.threads.remove(&id) {
+                if let Some(stack) = thread.take_stack() {
+                    stacks.push(stack);
+                }
+            }
+        }
+
+        stacks
+    }
+
+    fn wake(&mut self, id: ThreadId) {
+        let idle = self.idle;
+        let current = self.current;
+
+        let thread = match self.threads.get_mut(&id) {
+            Some(thread) => thread,
+            None => return,
+        };
+
+        match thread.state {
+            ThreadState::Blocked => {
+                thread.state = ThreadState::Ready;
+                thread.ready_since = time::now_nanos();
+                self.policy.enqueue(id, true);
+
+                if current == idle {
+                    per_cpu!(need_resched = 1);
+                }
+            }
+            ThreadState::Ready | ThreadState::Running => thread.wakeup_pending = true,
+            ThreadState::Dead => {}
+        }
+    }
+}
+
+static SCHEDULER: Mutex<Option<Scheduler>> = Mutex::new(None);
+
+fn with_scheduler<F, R>(f: F) -> Option<R> where F: FnOnce(&mut Scheduler) -> R {
+    int::without_interrupts(|| SCHEDULER.lock().as_mut().map(f))
+}
+
+// make the running code the boot thread, and start the idle thread
+pub fn init() {
+    let idle_stack = mem::alloc_stack("idle", THREAD_STACK_PAGES).expect("could not allocate idle stack");
+
+    let mut scheduler = Scheduler {
+        threads: BTreeMap::new(),
+        policy: Box::new(Fair::new()),
+        current: BOOT_THREAD,
+        idle: ThreadId(1),
+        next_id: 2,
+        switched_in: time::now_nanos(),
+        dead: Vec::new(),
+    };
+
+    let mut boot = Box::new(Thread::new(BOOT_THREAD, "main", None, None));
+    boot.state = ThreadState::Running;
+    scheduler.threads.insert(BOOT_THREAD, boot);
+    scheduler.policy.add(BOOT_THREAD, 0);
+
+    let idle = new_thread(ThreadId(1), "idle", idle_stack, Box::new(idle_loop));
+    scheduler.add(idle);
+
+    int::without_interrupts(|| {
+        *SCHEDULER.lock() = Some(scheduler);
+        per_cpu!(current_thread = BOOT_THREAD.0);
+    });
+
+    vga::okay();
+    write!(Writer::new(), "Initialised the scheduler, {} policy\n", scheduler_policy_name())
+        .expect("Unexpected failure in write!()");
+}
+
+fn new_thread(id: ThreadId, name: &'static str, stack: Stack, entry: ThreadEntry) -> Box<Thread> {
+    let mut thread = Box::new(Thread::new(id, name, Some(stack), Some(entry)));
+    thread.rsp = unsafe { context::init_stack(stack.top(), id.0) };
+    thread
+}
+
+// start a kernel thread running a closure
+pub fn spawn<F>(name: &'static str, f: F) -> ThreadId where F: FnOnce() + Send + 'static {
+    // the entry is only ever called once
+    let mut f = Some(f);
+    let entry: ThreadEntry = Box::new(move || (f.take().unwrap())());
+
+    let stack = mem::alloc_stack(name, THREAD_STACK_PAGES).expect("could not allocate thread stack");
+
+    with_scheduler(|scheduler| {
+        let id = scheduler.allocate_id();
+        scheduler.add(new_thread(id, name, stack, entry));
+        id
+    }).expect("scheduler not initialised")
+}
+
+// run a thread in the page table with the given P4, or the kernel's own if
+// none. Takes effect at once for the running thread
+pub fn set_page_table(id: ThreadId, page_table: Option<PhysicalAddress>) {
+    with_scheduler(|scheduler| {
+        scheduler.thread(id).page_table = page_table;
+        if id == scheduler.current {
+            mem::load_page_table(page_table.unwrap_or_else(mem::kernel_page_table));
+        }
+    }).expect("scheduler not initialised");
+}
+
+// give up the CPU, to the next ready thread if there is one
+pub fn schedule() {
+    switch(false);
+}
+
+fn switch(preempted: bool) {
+    int::without_interrupts(|| {
+        let switch = SCHEDULER.lock().as_mut()
+            .and_then(|scheduler| scheduler.switch_next(preempted));
+
+        if let Some((old_rsp, new_rsp)) = switch {
+            unsafe { context::switch(old_rsp, new_rsp); }
+            finish_switch();
+        }
+    });
+}
+
+// run on the thread switched to, once the previous one is off its stack
+fn finish_switch() {
+    let stacks = SCHEDULER.lock().as_mut().map_or(Vec::new(), |scheduler| scheduler.reap());
+
+    for stack in stacks {
+        mem::free_stack(stack);
+    }
+}
+
+// entered through thread_trampoline when a thread first runs, with
+// interrupts disabled
+#[no_mangle]
+pub extern "C" fn thread_start(_id: usize) -> ! {
+    finish_switch();
+
+    let entry = SCHEDULER.lock().as_mut()
+        .and_then(|scheduler| {
+            let current = scheduler.current;
+            scheduler.thread(current).take_entry()
+        });
+
+    int::enable();
+
+    if let Some(mut entry) = entry {
+        entry();
+    }
+
+    exit();
+}
+
+fn idle_loop() {
+    loop {
+        int::enable();
+        unsafe { asm!("hlt" :::: "volatile"); }
+    }
+}
+
+pub fn yield_now() {
+    schedule();
+}
+
+// end the current thread
+pub fn exit() -> ! {
+    with_scheduler(|scheduler| {
+        let current = scheduler.current;
+        assert!(current != BOOT_THREAD && current != scheduler.idle, "thread cannot exit");
+
+        scheduler.thread(current).state = ThreadState::Dead;
+        scheduler.policy.remove(current);
+        scheduler.dead.push(current);
+    });
+
+    schedule();
+    unreachable!("dead thread was scheduled");
+}
+
+// wait until another thread or an interrupt handler calls wake
+pub fn block() {
+    int::without_interrupts(|| {
+        let blocked = with_scheduler(|scheduler| {
+            let current = scheduler.current;
+            let thread = scheduler.thread(current);
+
+            if thread.wakeup_pending {
+                thread.wakeup_pending = false;
+                false
+            } else {
+                thread.state = ThreadState::Blocked;
+                true
+            }
+        });
+
+        if blocked == Some(true) {
+            schedule();
+        }
+    });
+}
+
+// make a blocked thread ready, or stop its next block from waiting
+pub fn wake(id: ThreadId) {
+    with_scheduler(|scheduler| scheduler.wake(id));
+}
+
+// read from the per-CPU area, so it never waits on the scheduler lock
+pub fn current_id() -> Option<ThreadId> {
+    match per_cpu!(current_thread) {
+        percpu::NO_THREAD => None,
+        id => Some(ThreadId(id)),
+    }
+}
+
+// block the current thread for at least the duration
+pub fn sleep(duration: Duration) {
+    let id = match current_id() {
+        Some(id) => id,
+        // no scheduler yet, just halt
+        None => return time::sleep(duration),
+    };
+
+    let deadline = Instant::now() + duration;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+
+        let timer = timer::after(deadline - now, move || wake(id));
+        block();
+        timer::cancel(timer);
+    }
+}
+
+// called from the timer interrupt, asks the policy whether the running
+// thread should give way
+pub fn timer_tick() {
+    let preempt = SCHEDULER.lock().as_mut().map_or(false, |scheduler| {
+        let current = scheduler.current;
+        let ran = time::now_nanos().saturating_sub(scheduler.switched_in);
+
+        if current == scheduler.idle {
+            scheduler.policy.has_ready()
+        } else {
+            scheduler.policy.should_preempt(current, ran)
+        }
+    });
+
+    if preempt {
+        per_cpu!(need_resched = 1);
+    }
+}
+
+// called at the end of every irq, after the EOI, to switch threads if a
+// handler asked for it. With preemption disabled the request is kept until
+// preempt_enable
+pub fn preempt() {
+    if !preemptible() {
+        return;
+    }
+
+    // only the CPU running threads may switch them, any other would save its
+    // own stack pointer into the thread running there
+    if per_cpu!(current_thread) == percpu::NO_THREAD {
+        return;
+    }
+
+    let resched = int::without_interrupts(|| {
+        let resched = per_cpu!(need_resched) != 0;
+        per_cpu!(need_resched = 0);
+        resched
+    });
+
+    if resched {
+        switch(true);
+    }
+}
+
+// keep the running thread on this CPU until the matching preempt_enable,
+// calls nest. Interrupts still arrive, but never switch threads
+pub fn preempt_disable() {
+    per_cpu!(preempt_count += 1);
+}
+
+pub fn preempt_enable() {
+    per_cpu!(preempt_count -= 1);
+    preempt();
+}
+
+pub fn preemptible() -> bool {
+    per_cpu!(preempt_count) == 0 && !percpu::in_interrupt()
+}
+
+// replace the scheduling policy, handing it every thread
+pub fn set_policy(mut policy: Box<dyn SchedulerPolicy>) {
+    with_scheduler(move |scheduler| {
+        for thread in scheduler.threads.values() {
+            if thread.id() == scheduler.idle || thread.state == ThreadState::Dead {
+                continue;
+            }
+
+            policy.add(thread.id(), thread.nice);
+            if thread.state == ThreadState::Ready {
+                policy.enqueue(thread.id(), false);
+            }
+        }
+
+        scheduler.policy = policy;
+    }).expect("scheduler not initialised");
+}
+
+pub fn scheduler_policy_name() -> &'static str {
+    with_scheduler(|scheduler| scheduler.policy.name()).unwrap_or("none")
+}
+
+// change a thread's priority, lower nice values get more CPU time
+pub fn set_nice(id: ThreadId, nice: i8) -> bool {
+    let nice = if nice < NICE_MIN { NICE_MIN } else if nice > NICE_MAX { NICE_MAX } else { nice };
+
+    with_scheduler(|scheduler| {
+        match scheduler.threads.get_mut(&id) {
+            Some(thread) => {
+                thread.nice = nice;
+                if id != scheduler.idle {
+                    scheduler.policy.set_nice(id, nice);
+                }
+                true
+            }
+            None => false,
+        }
+    }).unwrap_or(false)
+}
+
+// snapshot of a thread, including its CPU accounting
+pub fn thread_info(id: ThreadId) -> Option<ThreadInfo> {
+    with_scheduler(|scheduler| {
+        let now = time::now_nanos();
+        let current = scheduler.current;
+        let switched_in = scheduler.switched_in;
+
+        scheduler.threads.get(&id).map(|thread| {
+            let mut info = thread.info();
+            // include the time the running thread has not yet been charged
+            if id == current {
+                info.stats.runtime += now.saturating_sub(switched_in);
+            }
+            info
+        })
+    }).and_then(|info| info)
+}
+
+pub fn threads() -> Vec<ThreadInfo> {
+    let ids: Vec<ThreadId> = with_scheduler(|scheduler| scheduler.threads.keys().cloned().collect())
+        .unwrap_or(Vec::new());
+
+    ids.into_iter().filter_map(thread_info).collect()
+}
+
+pub fn print_threads() {
+    write!(Writer::new(), "  {:>3} {:<16} {:<8} {:>4} {:>10} {:>10} {:>8}\n",
+        "id", "name", "state", "nice", "run (us)", "wait (us)", "switches")
+        .expect("Unexpected failure in write!()");
+
+    for info in threads() {
+        write!(Writer::new(), "  {:>3} {:<16} {:<8} {:>4} {:>10} {:>10} {:>8}\n",
+            info.id.0, info.name, format_state(info.state), info.nice,
+            info.stats.runtime / 1000, info.stats.wait_time / 1000, info.stats.context_switches)
+            .expect("Unexpected failure in write!()");
+    }
+}
+
+fn format_state(state: ThreadState) -> &'static str {
+    match state {
+        ThreadState::Ready => "ready",
+        ThreadState::Running => "running",
+        ThreadState::Blocked => "blocked",
+        ThreadState::Dead => "dead",
+    }
+}
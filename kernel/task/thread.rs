@@ -0,0 +1,121 @@
+// task/thread.rs
+// a kernel thread: its own stack and the state needed to resume it
+
+use core::fmt;
+use alloc::boxed::Box;
+use arch::x86_64::mem::PhysicalAddress;
+use arch::x86_64::mem::stack::Stack;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(pub usize);
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    // waiting in the run queue
+    Ready,
+    Running,
+    // waiting for a wake
+    Blocked,
+    // exited, its stack is freed once another thread runs
+    Dead,
+}
+
+pub type ThreadEntry = Box<dyn FnMut() + Send>;
+
+// CPU accounting, all times in nanoseconds
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadStats {
+    // time spent running
+    pub runtime: u64,
+    // time spent ready, waiting for the CPU
+    pub wait_time: u64,
+    // times the thread was switched out
+    pub context_switches: u64,
+    // of those, how many were preemptions rather than yields or blocks
+    pub preemptions: u64,
+}
+
+// a snapshot of a thread, for reporting
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: &'static str,
+    pub state: ThreadState,
+    pub nice: i8,
+    pub stats: ThreadStats,
+}
+
+pub struct Thread {
+    id: ThreadId,
+    name: &'static str,
+    pub state: ThreadState,
+    // saved stack pointer while the thread is not running
+    pub rsp: usize,
+    // none for the boot thread, which runs on the boot stack
+    stack: Option<Stack>,
+    // taken by the thread when it first runs
+    entry: Option<ThreadEntry>,
+    // a wake arrived while the thread was not blocked
+    pub wakeup_pending: bool,
+    pub nice: i8,
+    pub stats: ThreadStats,
+    // when the thread last became ready, on the monotonic clock
+    pub ready_since: u64,
+    // P4 table loaded while the thread runs, none for the kernel's own
+    pub page_table: Option<PhysicalAddress>,
+}
+
+impl Thread {
+    pub fn new(id: ThreadId, name: &'static str, stack: Option<Stack>,
+               entry: Option<ThreadEntry>) -> Thread {
+        Thread {
+            id: id,
+            name: name,
+            state: ThreadState::Ready,
+            rsp: 0,
+            stack: stack,
+            entry: entry,
+            wakeup_pending: false,
+            nice: 0,
+            stats: ThreadStats::default(),
+            ready_since: 0,
+            page_table: None,
+        }
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn stack(&self) -> Option<Stack> {
+        self.stack
+    }
+
+    pub fn take_stack(&mut self) -> Option<Stack> {
+        self.stack.take()
+    }
+
+    pub fn take_entry(&mut self) -> Option<ThreadEntry> {
+        self.entry.take()
+    }
+
+    pub fn info(&self) -> ThreadInfo {
+        ThreadInfo {
+            id: self.id,
+            name: self.name,
+            state: self.state,
+            nice: self.nice,
+            stats: self.stats,
+        }
+    }
+}
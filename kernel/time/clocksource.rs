@@ -0,0 +1,104 @@
+// time/clocksource.rs
+// selection of the counter behind the monotonic clock
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::RwLock;
+use arch::dev::hpet;
+use arch::x86_64::int::int;
+use arch::x86_64::tsc;
+use driver::vga;
+use driver::vga::Writer;
+use time;
+
+pub trait ClockSource: Sync {
+    fn name(&self) -> &'static str;
+
+    // nanoseconds from an arbitrary, fixed starting point
+    fn nanos(&self) -> u64;
+
+    // nanoseconds between two distinct readings
+    fn resolution(&self) -> u64;
+}
+
+// the timer interrupt tick count, always available once the PIT runs
+pub struct TickClock;
+
+pub static TICK_CLOCK: TickClock = TickClock;
+
+impl ClockSource for TickClock {
+    fn name(&self) -> &'static str {
+        "PIT tick"
+    }
+
+    fn nanos(&self) -> u64 {
+        time::uptime_nanos()
+    }
+
+    fn resolution(&self) -> u64 {
+        time::duration_as_nanos(time::tick_period())
+    }
+}
+
+// source in use, and the offset that keeps the clock running on from where
+// the previous source left off
+static CLOCK: RwLock<(&'static dyn ClockSource, u64)> = RwLock::new((&TICK_CLOCK, 0));
+
+// highest value returned so far, so readings never go backwards
+static LAST: AtomicUsize = AtomicUsize::new(0);
+
+pub fn current() -> &'static dyn ClockSource {
+    CLOCK.read().0
+}
+
+// switch the monotonic clock over to another source
+pub fn select(source: &'static dyn ClockSource) {
+    int::without_interrupts(|| {
+        let now = now();
+        let offset = now.wrapping_sub(source.nanos());
+        *CLOCK.write() = (source, offset);
+    });
+}
+
+// nanoseconds since boot, from the best available source
+pub fn now() -> u64 {
+    let (source, offset) = *CLOCK.read();
+    let nanos = source.nanos().wrapping_add(offset) as usize;
+
+    let mut last = LAST.load(Ordering::Relaxed);
+    while nanos > last {
+        match LAST.compare_exchange_weak(last, nanos, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return nanos as u64,
+            Err(current) => last = current,
+        }
+    }
+
+    last as u64
+}
+
+// pick the best clock source: an invariant TSC, then the HPET, then the tick
+// count. The TSC is calibrated against the HPET if there is one, otherwise
+// against the tick count, which needs interrupts enabled.
+pub fn init() {
+    let hpet = hpet::init();
+    let reference: &'static dyn ClockSource = match hpet {
+        Some(hpet) => hpet,
+        None => &TICK_CLOCK,
+    };
+
+    let best: &'static dyn ClockSource = if let Some(tsc) = tsc::init(reference) {
+        vga::okay();
+        write!(Writer::new(), "Calibrated the TSC against the {}, {} kHz\n",
+            reference.name(), tsc.frequency() / 1000)
+            .expect("Unexpected failure in write!()");
+        tsc
+    } else {
+        reference
+    };
+
+    select(best);
+
+    vga::okay();
+    write!(Writer::new(), "Clock source: {}, resolution {} ns\n", best.name(), best.resolution())
+        .expect("Unexpected failure in write!()");
+}
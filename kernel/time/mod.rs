@@ -0,0 +1,90 @@
+// time/mod.rs
+// monotonic time since boot, driven by the timer interrupt
+
+pub mod instant;
+pub mod timer;
+pub mod clocksource;
+pub mod system;
+
+pub use core::time::Duration;
+pub use self::instant::Instant;
+pub use self::system::{SystemTime, UNIX_EPOCH};
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use arch::x86_64::int::int;
+
+pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+// nanoseconds since boot, advanced by one tick period per timer interrupt
+static NANOS: AtomicUsize = AtomicUsize::new(0);
+// number of timer interrupts since boot
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+// length of a tick in nanoseconds, set when the timer is programmed
+static TICK_NANOS: AtomicUsize = AtomicUsize::new(0);
+
+// called by the timer driver whenever it changes its rate
+pub fn set_tick_period(nanos: u64) {
+    TICK_NANOS.store(nanos as usize, Ordering::Relaxed);
+}
+
+pub fn tick_period() -> Duration {
+    Duration::from_nanos(TICK_NANOS.load(Ordering::Relaxed) as u64)
+}
+
+// called from the timer interrupt
+pub fn tick() {
+    NANOS.fetch_add(TICK_NANOS.load(Ordering::Relaxed), Ordering::Relaxed);
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) as u64 + 1;
+
+    timer::run_expired(ticks);
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed) as u64
+}
+
+// nanoseconds since boot, from the selected clock source
+pub fn now_nanos() -> u64 {
+    clocksource::now()
+}
+
+// nanoseconds since boot, with the resolution of a timer tick
+pub fn uptime_nanos() -> u64 {
+    NANOS.load(Ordering::Relaxed) as u64
+}
+
+pub fn uptime() -> Duration {
+    Duration::from_nanos(uptime_nanos())
+}
+
+pub fn duration_as_nanos(duration: Duration) -> u64 {
+    duration.as_secs() * NANOS_PER_SEC + duration.subsec_nanos() as u64
+}
+
+// whole ticks needed to cover a duration, at least one
+pub fn duration_as_ticks(duration: Duration) -> u64 {
+    let tick = TICK_NANOS.load(Ordering::Relaxed) as u64;
+    assert!(tick != 0, "timer not running");
+
+    let ticks = (duration_as_nanos(duration) + tick - 1) / tick;
+    if ticks == 0 { 1 } else { ticks }
+}
+
+// busy wait, for short hardware delays and where interrupts may be off.
+// Only as precise as the clock source.
+pub fn delay(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        unsafe { asm!("pause" :::: "volatile"); }
+    }
+}
+
+// halt the CPU until the duration has passed, waking on every interrupt
+pub fn sleep(duration: Duration) {
+    assert!(int::are_enabled(), "sleep with interrupts disabled would never wake");
+
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        unsafe { asm!("hlt" :::: "volatile"); }
+    }
+}
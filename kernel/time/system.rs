@@ -0,0 +1,159 @@
+// time/system.rs
+// wall-clock time, read from the RTC at boot and advanced by the monotonic clock
+
+use core::fmt;
+use core::ops::{Add, Sub};
+use spin::Once;
+use arch::dev::rtc;
+use time::{Duration, Instant, NANOS_PER_SEC, duration_as_nanos};
+
+const SECS_PER_DAY: i64 = 86400;
+
+// nanoseconds since the UNIX epoch, 1970-01-01 00:00:00 UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemTime(u64);
+
+pub const UNIX_EPOCH: SystemTime = SystemTime(0);
+
+// wall-clock time at a known instant on the monotonic clock
+static BOOT_TIME: Once<(SystemTime, Instant)> = Once::new();
+
+impl SystemTime {
+    // the epoch until the RTC has been read
+    pub fn now() -> SystemTime {
+        match BOOT_TIME.try() {
+            Some(&(time, instant)) => time + instant.elapsed(),
+            None => UNIX_EPOCH,
+        }
+    }
+
+    pub fn from_unix_secs(secs: u64) -> SystemTime {
+        SystemTime(secs * NANOS_PER_SEC)
+    }
+
+    pub fn unix_secs(&self) -> u64 {
+        self.0 / NANOS_PER_SEC
+    }
+
+    // Err holds how far the other time is ahead of this one
+    pub fn duration_since(&self, earlier: SystemTime) -> Result<Duration, Duration> {
+        if self.0 >= earlier.0 {
+            Ok(Duration::from_nanos(self.0 - earlier.0))
+        } else {
+            Err(Duration::from_nanos(earlier.0 - self.0))
+        }
+    }
+
+    pub fn elapsed(&self) -> Result<Duration, Duration> {
+        SystemTime::now().duration_since(*self)
+    }
+
+    pub fn date_time(&self) -> DateTime {
+        DateTime::from_unix_secs(self.unix_secs())
+    }
+}
+
+impl Add<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn add(self, duration: Duration) -> SystemTime {
+        SystemTime(self.0 + duration_as_nanos(duration))
+    }
+}
+
+impl Sub<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn sub(self, duration: Duration) -> SystemTime {
+        SystemTime(self.0 - duration_as_nanos(duration))
+    }
+}
+
+impl fmt::Display for SystemTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.date_time())
+    }
+}
+
+// a UTC calendar date and time of day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    // days between the epoch and a date of the proleptic Gregorian calendar
+    fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = if year >= 0 { year } else { year - 399 } / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+        era * 146097 + day_of_era - 719468
+    }
+
+    // inverse of days_from_civil
+    fn civil_from_days(days: i64) -> (i64, i64, i64) {
+        let days = days + 719468;
+        let era = if days >= 0 { days } else { days - 146096 } / 146097;
+        let day_of_era = days - era * 146097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+            - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        (year, month, day)
+    }
+
+    pub fn from_unix_secs(secs: u64) -> DateTime {
+        let secs = secs as i64;
+        let (year, month, day) = DateTime::civil_from_days(secs / SECS_PER_DAY);
+        let time = secs % SECS_PER_DAY;
+
+        DateTime {
+            year: year as u32,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    // dates before the epoch clamp to it
+    pub fn unix_secs(&self) -> u64 {
+        let days = DateTime::days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        let secs = days * SECS_PER_DAY
+            + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+
+        if secs < 0 { 0 } else { secs as u64 }
+    }
+
+    pub fn system_time(&self) -> SystemTime {
+        SystemTime::from_unix_secs(self.unix_secs())
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+// set the wall clock from the RTC, assumed to hold UTC
+pub fn init() -> Option<SystemTime> {
+    let now = rtc::read_date_time()?.system_time();
+    BOOT_TIME.call_once(|| (now, Instant::now()));
+
+    Some(now)
+}
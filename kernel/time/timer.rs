@@ -0,0 +1,255 @@
+// time/timer.rs
+// hashed timer wheel for one-shot and periodic callbacks
+
+//***************************************//
+// Callbacks run in interrupt context,   //
+// with interrupts disabled. They must   //
+// be short and must not sleep. They can //
+// cancel timers, but adding one would   //
+// allocate, so after and every panic    //
+// when called from an interrupt         //
+//                                       //
+// Nothing on the interrupt path touches //
+// the heap: room for every move it can  //
+// make is reserved when a timer is      //
+// added, and finished timers are only   //
+// freed outside interrupt context       //
+//***************************************//
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use spin::Mutex;
+use arch::x86_64::int::int;
+use arch::x86_64::percpu;
+use time::{Duration, duration_as_ticks, ticks};
+
+// number of slots, a timer due further ahead waits for later rotations
+const WHEEL_SIZE: usize = 256;
+
+pub type TimerCallback = Box<dyn FnMut() + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId(usize);
+
+struct Timer {
+    id: TimerId,
+    // tick at which the timer fires
+    deadline: u64,
+    // ticks between firings of a periodic timer
+    period: Option<u64>,
+    callback: TimerCallback,
+}
+
+struct TimerWheel {
+    slots: Vec<Vec<Timer>>,
+    // last tick whose slot has been processed
+    current: u64,
+    // timer whose callback is running, and whether it was cancelled meanwhile
+    running: Option<TimerId>,
+    running_cancelled: bool,
+    // timers in the wheel or running, and how many of those are periodic
+    pending: usize,
+    periodic: usize,
+    // fired or cancelled timers, whose callbacks are dropped outside the
+    // interrupt. It keeps room for every pending timer
+    finished: Vec<Timer>,
+}
+
+impl TimerWheel {
+    fn new() -> TimerWheel {
+        let mut slots = Vec::with_capacity(WHEEL_SIZE);
+        for _ in 0..WHEEL_SIZE {
+            slots.push(Vec::new());
+        }
+
+        TimerWheel {
+            slots: slots,
+            current: ticks(),
+            running: None,
+            running_cancelled: false,
+            pending: 0,
+            periodic: 0,
+            finished: Vec::new(),
+        }
+    }
+
+    fn slot_for(&self, deadline: u64) -> (u64, usize) {
+        // a deadline already passed is fired on the next tick
+        let deadline = if deadline <= self.current { self.current + 1 } else { deadline };
+        (deadline, deadline as usize % WHEEL_SIZE)
+    }
+
+    // put a timer in its slot, which must have room for it if called from
+    // the interrupt
+    fn insert(&mut self, timer: Timer) {
+        let (deadline, slot) = self.slot_for(timer.deadline);
+        self.slots[slot].push(Timer { deadline: deadline, ..timer });
+    }
+
+    // add a new timer, from thread context. A periodic timer can move into
+    // any slot when it fires, so every slot keeps room for all of them on
+    // top of the timers already in it
+    fn add(&mut self, timer: Timer) {
+        self.pending += 1;
+        if timer.period.is_some() {
+            self.periodic += 1;
+        }
+
+        self.insert(timer);
+
+        let (pending, periodic) = (self.pending, self.periodic);
+        self.finished.reserve(pending);
+        for slot in self.slots.iter_mut() {
+            slot.reserve(periodic);
+        }
+    }
+
+    // a timer is done with, either fired for the last time or cancelled
+    fn retire(&mut self, timer: Timer) {
+        self.pending -= 1;
+        if timer.period.is_some() {
+            self.periodic -= 1;
+        }
+        self.finished.push(timer);
+    }
+
+    fn remove(&mut self, id: TimerId) -> Option<Timer> {
+        for slot in self.slots.iter_mut() {
+            if let Some(index) = slot.iter().position(|timer| timer.id == id) {
+                return Some(slot.swap_remove(index));
+            }
+        }
+
+        None
+    }
+
+    fn cancel(&mut self, id: TimerId) -> bool {
+        if let Some(timer) = self.remove(id) {
+            self.retire(timer);
+            return true;
+        }
+
+        // a timer is out of the wheel while its callback runs
+        if self.running == Some(id) && !self.running_cancelled {
+            self.running_cancelled = true;
+            return true;
+        }
+
+        false
+    }
+
+    // take out the next timer due up to and including the given tick,
+    // moving on through the slots as they empty
+    fn pop_expired(&mut self, now: u64) -> Option<Timer> {
+        loop {
+            let current = self.current;
+            {
+                let slot = &mut self.slots[current as usize % WHEEL_SIZE];
+                if let Some(index) = slot.iter().position(|timer| timer.deadline <= current) {
+                    return Some(slot.swap_remove(index));
+                }
+            }
+
+            if current >= now {
+                return None;
+            }
+            self.current += 1;
+        }
+    }
+
+    // called once a timer's callback has run, from the interrupt
+    fn finish(&mut self, mut timer: Timer) {
+        let cancelled = self.running_cancelled;
+        self.running = None;
+
+        match timer.period {
+            Some(period) if !cancelled => {
+                timer.deadline += period;
+                self.insert(timer);
+            }
+            _ => self.retire(timer),
+        }
+    }
+}
+
+// created by the first timer added, so the interrupt never builds it
+static WHEEL: Mutex<Option<TimerWheel>> = Mutex::new(None);
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+// run a closure against the wheel with interrupts disabled, as it is also
+// locked from the timer interrupt. Finished timers are freed afterwards,
+// unless this is a callback running in the interrupt
+fn with_wheel<F, R>(f: F) -> R where F: FnOnce(&mut TimerWheel) -> R {
+    int::without_interrupts(|| {
+        let mut wheel = WHEEL.lock();
+        let wheel = wheel.get_or_insert_with(TimerWheel::new);
+        let result = f(wheel);
+
+        if !percpu::in_interrupt() {
+            wheel.finished.clear();
+        }
+        result
+    })
+}
+
+fn add(delay: Duration, period: Option<Duration>, callback: TimerCallback) -> TimerId {
+    let id = TimerId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let timer = Timer {
+        id: id,
+        deadline: ticks() + duration_as_ticks(delay),
+        period: period.map(duration_as_ticks),
+        callback: callback,
+    };
+
+    with_wheel(|wheel| wheel.add(timer));
+    id
+}
+
+// run a callback once, after a delay
+pub fn after<F>(delay: Duration, callback: F) -> TimerId where F: FnMut() + Send + 'static {
+    assert!(!percpu::in_interrupt(), "timers cannot be added in interrupt context");
+    add(delay, None, Box::new(callback))
+}
+
+// run a callback repeatedly, first after one period
+pub fn every<F>(period: Duration, callback: F) -> TimerId where F: FnMut() + Send + 'static {
+    assert!(!percpu::in_interrupt(), "timers cannot be added in interrupt context");
+    add(period, Some(period), Box::new(callback))
+}
+
+// stop a timer, returns false if it had already fired or been cancelled
+pub fn cancel(id: TimerId) -> bool {
+    with_wheel(|wheel| wheel.cancel(id))
+}
+
+// called from the timer interrupt
+pub fn run_expired(now: u64) {
+    // callbacks run without the lock, so they can cancel timers
+    loop {
+        let timer = {
+            let mut wheel = WHEEL.lock();
+            let wheel = match wheel.as_mut() {
+                Some(wheel) => wheel,
+                None => return,
+            };
+
+            match wheel.pop_expired(now) {
+                Some(timer) => {
+                    wheel.running = Some(timer.id);
+                    wheel.running_cancelled = false;
+                    timer
+                }
+                None => return,
+            }
+        };
+
+        let mut timer = timer;
+        (timer.callback)();
+
+        if let Some(wheel) = WHEEL.lock().as_mut() {
+            wheel.finish(timer);
+        }
+    }
+}
@@ -1,2 +1,4 @@
+//only built for test runs, QEMU must be started with the isa-debug-exit device
+#[cfg(feature = "qemu-exit")]
 pub mod qemu;
 